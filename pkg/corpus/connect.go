@@ -26,9 +26,10 @@ func ConnectToExisting(datasource string) (db *sql.DB, err error) {
 		if err := rows.Scan(&major, &minor); err != nil {
 			return db, err
 		}
-		// TODO: accept same major version
-		if major != MAJOR || minor != MINOR { // HACK: expects exact version
-			return db, fmt.Errorf("expected version 0.0, got %d.%d", major, minor)
+		// minor versions only add tables or columns, so any minor version at or
+		// after the one these queries were written against will do
+		if major != MAJOR || minor < MINOR {
+			return db, fmt.Errorf("expected version %d.%d+, got %d.%d", MAJOR, MINOR, major, minor)
 		}
 	}
 
@@ -3,7 +3,9 @@ CREATE TABLE schema_version (
   , minor INT4 -- there's a new table or column
   , CONSTRAINT schema_version_pkey PRIMARY KEY (major, minor)
 );
-INSERT INTO schema_version VALUES (0, 0);
+-- when changing the schema, bump this along with SCHEMA_VERSION and add a
+-- migration to MIGRATIONS in scripts/splitter/src/sqlite.rs
+INSERT INTO schema_version VALUES (0, 12);
 
 CREATE TABLE languages (
     id INTEGER PRIMARY KEY -- TODO: make xxhash(name)? Not worth it for now
@@ -23,7 +25,7 @@ INSERT INTO languages VALUES
 
 -- for coordinating compatibility:
 CREATE TABLE versions(
-  id INTEGER PRIMARY KEY -- xxhash64(family, version)
+  id INTEGER PRIMARY KEY -- xxhash3_64 of "family:version"
   , family TEXT -- I've got aspirations to expand this suite to cover other SQLs
   , "version" TEXT
   , CONSTRAINT unique_version UNIQUE (family, version)
@@ -40,6 +42,8 @@ CREATE TABLE language_versions (
 CREATE TABLE statements (
   id INTEGER PRIMARY KEY      -- the xxhash of the text
   , "text" TEXT NOT NULL
+  , xxh3_128 BLOB -- the big-endian xxhash3_128 of the text, if splitter was run
+                  -- with --id-hash xxh3-128. For verifying that ids are unique.
 );
 
 CREATE TABLE statement_languages (
@@ -50,12 +54,39 @@ CREATE TABLE statement_languages (
   , CONSTRAINT statement_languages_pkey PRIMARY KEY (language_id, statement_id)
 );
 
+-- PL bodies extracted from DO blocks and CREATE FUNCTION statements, so that
+-- oracles can test each body with and without its wrapper.
+CREATE TABLE statement_children(
+    parent_id INTEGER REFERENCES statements(id)
+  , child_id INTEGER REFERENCES statements(id)
+  , start_offset INTEGER -- byte offset of the child within the parent's text.
+  , end_offset INTEGER   -- Null if the body isn't verbatim, e.g. a quoted string with escapes.
+  , CONSTRAINT statement_children_pkey PRIMARY KEY (parent_id, child_id)
+);
+CREATE INDEX statement_parents ON statement_children(child_id, parent_id);
+
 CREATE TABLE statement_fingerprints(
     fingerprint INTEGER
   , statement_id INTEGER REFERENCES statements(id)
   , CONSTRAINT statement_fingerprints_pkey PRIMARY KEY (statement_id, fingerprint)
 );
 
+CREATE TABLE statement_normalized(
+    statement_id INTEGER PRIMARY KEY REFERENCES statements(id)
+  , normalized TEXT NOT NULL -- pg_query's normalized text, with constants
+                             -- replaced by $1, $2, ...
+);
+CREATE INDEX normalized_statements ON statement_normalized(normalized, statement_id);
+
+-- only populated if splitter was run with --store-ast
+CREATE TABLE statement_asts(
+    statement_id INTEGER REFERENCES statements(id)
+  , pg_query_version INTEGER -- libpg_query's PG_VERSION_NUM, e.g. 130003
+  , protobuf BLOB NOT NULL -- a protobuf-encoded pg_query.ParseResult; see
+                           -- libpg_query's protobuf/pg_query.proto
+  , CONSTRAINT statement_asts_pkey PRIMARY KEY (statement_id, pg_query_version)
+);
+
 -- TODO: delete?
 CREATE TABLE statement_versions(
     statement_id INT8 REFERENCES statements(id)
@@ -76,9 +107,49 @@ CREATE TABLE urls (
   , license_id TEXT REFERENCES licenses(id)
 );
 
+-- each invocation of the splitter that wrote to this db, for telling which
+-- documents were split by an older version of it. Unlike the rest of the db,
+-- these rows and documents.run_id differ between otherwise identical runs.
+CREATE TABLE runs(
+    id INTEGER PRIMARY KEY -- xxhash3_64 of the start time and command line
+  , splitter_version TEXT
+  , splitter_commit TEXT -- the last git commit to the splitter's source, or
+                         -- "unknown" if it wasn't built from a checkout
+  , psql_splitter_commit TEXT -- the locked git commit
+  , pg_query_wrapper_commit TEXT -- the locked git commit
+  , libpg_query_version TEXT -- e.g. "13.3"
+  , command_line TEXT -- a json array of the arguments
+  , input_roots TEXT -- a json array of the files, directories, or tarballs read
+  , started_at TEXT -- e.g. "2021-11-28T17:04:05.123Z"
+  , finished_at TEXT -- when the run committed
+);
+
 -- this is dumb. Maybe eliminate in favor of indices?
 CREATE TABLE documents (
-    id INTEGER PRIMARY KEY -- xxhash_64 of the document
+    id INTEGER PRIMARY KEY -- xxhash_64 of the document, transcoded to utf-8
+  , "encoding" TEXT -- the encoding of the original file, e.g. "UTF-8" or "EUC-JP".
+                    -- Statement offsets are in bytes of the original file.
+  , run_id INTEGER REFERENCES runs(id) -- the run that last split the document
+  , complete BOOLEAN NOT NULL DEFAULT 0 -- whether all of the document's rows
+                                        -- were written. `splitter repair`
+                                        -- rewrites incomplete documents.
+);
+
+-- the versions for which each document was ingested with --pg-version
+CREATE TABLE document_versions(
+    document_id INTEGER REFERENCES documents(id)
+  , version_id INTEGER REFERENCES versions(id)
+  , CONSTRAINT document_versions_pkey PRIMARY KEY (document_id, version_id)
+);
+CREATE INDEX version_documents ON document_versions(version_id, document_id);
+
+-- documents that psql_splitter could only partially split. With --best-effort,
+-- the remainder is stored as a single statement in the language "other".
+CREATE TABLE split_failures (
+    document_id INTEGER PRIMARY KEY REFERENCES documents(id)
+  , start_offset INTEGER -- where splitting stopped, in bytes of the original file
+  , start_line INTEGER
+  , remainder_preview TEXT -- the first few characters of the remainder
 );
 
 CREATE TABLE document_urls(
@@ -88,22 +159,33 @@ CREATE TABLE document_urls(
 );
 CREATE UNIQUE INDEX urls_for_document ON document_urls(url_id, document_id);
 
+-- A PL body whose text doesn't appear verbatim in the document (e.g. a quoted
+-- string with doubled quotes) has the span of the statement that defines it.
 CREATE TABLE document_statements (
     document_id INTEGER REFERENCES documents(id)
   , statement_id INTEGER REFERENCES statements(id)
-  , start_line INTEGER
-  , start_offset INTEGER
-  , end_line INTEGER
-  , end_offset INTEGER
+  , start_line INTEGER -- 1-indexed line of the statement's first char
+  , start_offset INTEGER -- in bytes of the original file
+  , end_line INTEGER -- 1-indexed line of the statement's last char, inclusive
+  , end_offset INTEGER -- in bytes of the original file, exclusive
   , locator TEXT -- which helps locate the statement within the page defined by the url.
-                 -- Usually null, since the line number and byte offset are usually enough
+                 -- Null unless a --url-template was given, in which case it's a
+                 -- direct link to the statement's lines from the first template.
+  -- the rest are null unless splitter was run with --unicode-positions
+  , start_column INTEGER -- 1-indexed column of the first char, in chars
+  , end_column INTEGER -- 1-indexed column of the last char, in chars
+  , start_char INTEGER -- offsets in unicode code points
+  , end_char INTEGER
+  , start_utf16 INTEGER -- offsets in utf-16 code units
+  , end_utf16 INTEGER
   , CONSTRAINT document_statement_source_pkey PRIMARY KEY (document_id, statement_id, start_offset)
 );
 CREATE INDEX statements_for_source ON document_statements(statement_id, document_id, start_offset);
 
 CREATE TABLE oracles(
-   id INTEGER PRIMARY KEY -- xxhash3_64 of the oracle name
-  , "name" TEXT -- e.g. "postgres 13 no-op do-block".
+   id INTEGER PRIMARY KEY -- xxhash64 (not xxhash3) of the oracle name
+  , "name" TEXT -- e.g. "postgres 13 no-op do-block". The splitter records
+                -- "pg_query (libpg_query 13.3)" for every pgsql statement.
 );
 
 CREATE TABLE predictions(
@@ -116,6 +198,9 @@ CREATE TABLE predictions(
                    -- json {syntax/parse tree, tokens}. This column is just
                    -- for debugging, so don't sweat it and probably don't try to
                    -- parse it unless you're confident of its structure.
+                   -- The splitter's pg_query oracle writes {"cursorpos": N},
+                   -- libpg_query's 1-indexed char position of the error, when
+                   -- it reported one.
   , valid BOOLEAN
   , CONSTRAINT predictions_pkey PRIMARY KEY (statement_id, oracle_id, language_id)
 );
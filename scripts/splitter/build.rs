@@ -0,0 +1,82 @@
+//! record the commits of the splitter and of the git dependencies that decide
+//! how statements are split and parsed, for the `runs` table
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// the commit after the `#` in a locked git package's `source`, e.g.
+/// `source = "git+https://github.com/SKalt/psql_splitter.git#a5b9238..."`
+fn locked_commit(lock: &str, package: &str) -> Option<String> {
+    let name = format!("name = \"{}\"", package);
+    let mut lines = lock.lines().skip_while(|line| *line != name);
+    lines.next()?;
+    for line in lines {
+        if line.is_empty() {
+            break; // the end of the package
+        }
+        if let Some(source) = line.strip_prefix("source = \"") {
+            return source
+                .rsplit('#')
+                .next()
+                .map(|s| s.trim_end_matches('"').to_string());
+        }
+    }
+    return None;
+}
+
+/// the output of a git command run in `dir`, if it succeeded
+fn git(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    let stdout = stdout.trim();
+    if stdout.is_empty() {
+        return None;
+    }
+    return Some(stdout.to_string());
+}
+
+/// the last commit to touch the splitter's own directory, so that commits
+/// elsewhere in the repo don't make documents stale
+fn splitter_commit(manifest_dir: &Path) -> Option<String> {
+    let git_dir = PathBuf::from(git(manifest_dir, &["rev-parse", "--absolute-git-dir"])?);
+    // HEAD moves on checkout and the index changes on every commit
+    println!("cargo:rerun-if-changed={}", git_dir.join("HEAD").display());
+    println!("cargo:rerun-if-changed={}", git_dir.join("index").display());
+    return git(manifest_dir, &["log", "-1", "--format=%H", "--", "."]);
+}
+
+fn main() {
+    let manifest_dir = PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap());
+    // the workspace's lockfile, or this package's if built on its own
+    let lock_path = [
+        manifest_dir.join("../../Cargo.lock"),
+        manifest_dir.join("Cargo.lock"),
+    ]
+    .iter()
+    .find(|path| path.exists())
+    .cloned();
+    let lock = match &lock_path {
+        Some(path) => {
+            println!("cargo:rerun-if-changed={}", path.display());
+            std::fs::read_to_string(path).unwrap_or_default()
+        }
+        None => String::new(),
+    };
+    let commit = splitter_commit(manifest_dir.as_path()).unwrap_or("unknown".to_string());
+    println!("cargo:rustc-env=SPLITTER_COMMIT={}", commit);
+    for (package, var) in [
+        ("psql_splitter", "PSQL_SPLITTER_COMMIT"),
+        ("pg_query_wrapper", "PG_QUERY_WRAPPER_COMMIT"),
+    ]
+    .iter()
+    {
+        let commit = locked_commit(lock.as_str(), package).unwrap_or("unknown".to_string());
+        println!("cargo:rustc-env={}={}", var, commit);
+    }
+}
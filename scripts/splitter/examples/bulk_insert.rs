@@ -0,0 +1,67 @@
+//! time splitting a synthetic 100k-statement document and writing it to an
+//! in-memory corpus db, which exercises `sqlite::batch_insert`'s chunking.
+//!
+//!     cargo run --release --example bulk_insert [n_statements]
+use rusqlite::Connection;
+use splitter::{
+    input::InputDocument,
+    sqlite::{self, SqliteWriter},
+    writer::{self, CorpusWriter},
+    IngestOptions, Splitter,
+};
+use std::time::Instant;
+
+fn main() -> Result<(), splitter::Failure> {
+    let n: usize = match std::env::args().nth(1) {
+        Some(n) => n.parse().expect("n_statements must be a number"),
+        None => 100_000,
+    };
+    // mostly near-identical inserts, like the regression suite's fixtures
+    let mut text = String::with_capacity(n * 48);
+    for i in 0..n {
+        match i % 4 {
+            0 => text.push_str(&format!("INSERT INTO t VALUES ({}, 'row {}');\n", i, i)),
+            1 => text.push_str(&format!("SELECT a, b FROM t WHERE a = {};\n", i)),
+            2 => text.push_str(&format!("UPDATE t SET b = 'x' WHERE a = {};\n", i)),
+            _ => text.push_str(&format!(
+                "-- comment {}\nDELETE FROM t WHERE a = {};\n",
+                i, i
+            )),
+        }
+    }
+    let splitter = Splitter {
+        urls: vec!["file:///{path}".to_string()],
+        ..Default::default()
+    };
+    let doc = splitter.decode(InputDocument {
+        path: "synthetic.sql".to_string(),
+        bytes: text.into_bytes(),
+    })?;
+
+    let start = Instant::now();
+    let split = splitter.split(&doc)?;
+    let split_time = start.elapsed();
+
+    let mut conn = Connection::open_in_memory()?;
+    sqlite::init(&mut conn)?;
+    let start = Instant::now();
+    {
+        let mut writer = SqliteWriter::new(&mut conn, &IngestOptions::default())?;
+        writer.begin_document(&doc, splitter.urls(doc.path())?.as_slice())?;
+        writer::write_split(&mut writer, &doc, &split)?;
+        writer.commit()?;
+    }
+    let write_time = start.elapsed();
+
+    let rows: i64 = conn.query_row("SELECT count(*) FROM document_statements", [], |row| {
+        row.get(0)
+    })?;
+    println!(
+        "{} statements: split in {:?}, wrote {} sources in {:?}",
+        split.statements().len(),
+        split_time,
+        rows,
+        write_time
+    );
+    return Ok(());
+}
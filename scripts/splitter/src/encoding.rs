@@ -0,0 +1,134 @@
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use lazy_static::lazy_static;
+use regex::bytes::Regex;
+
+use crate::Failure;
+
+lazy_static! {
+    static ref DECLARED_ENCODING: Regex = Regex::new(
+        r#"(?im)^\s*(?:set\s+client_encoding\s*(?:to|=)\s*['"]?|\\encoding\s+)([a-z0-9_\-]+)"#
+    )
+    .unwrap();
+}
+
+/// look up a postgres encoding name, e.g. `LATIN1`, `EUC_JP`, or `WIN1252`
+pub fn pg_encoding(name: &str) -> Option<&'static Encoding> {
+    let normalized: String = name
+        .to_lowercase()
+        .chars()
+        .filter(|c| *c != '_' && *c != '-')
+        .collect();
+    let label = match normalized.as_str() {
+        "utf8" | "unicode" => "utf-8",
+        // no conversion is done for SQL_ASCII, so any byte goes
+        "sqlascii" | "latin1" => "windows-1252",
+        "latin2" => "iso-8859-2",
+        "latin3" => "iso-8859-3",
+        "latin4" => "iso-8859-4",
+        "latin5" => "iso-8859-9",
+        "latin6" => "iso-8859-10",
+        "latin7" => "iso-8859-13",
+        "latin8" => "iso-8859-14",
+        "latin9" => "iso-8859-15",
+        "latin10" => "iso-8859-16",
+        "iso88595" => "iso-8859-5",
+        "iso88596" => "iso-8859-6",
+        "iso88597" => "iso-8859-7",
+        "iso88598" => "iso-8859-8",
+        "win866" => "ibm866",
+        "win874" => "windows-874",
+        "koi8" | "koi8r" => "koi8-r",
+        "koi8u" => "koi8-u",
+        "eucjp" | "eucjis2004" => "euc-jp",
+        "euckr" | "uhc" => "euc-kr",
+        "euccn" => "gb2312",
+        "sjis" | "shiftjis2004" => "shift_jis",
+        other => {
+            if other.starts_with("win") {
+                return Encoding::for_label(format!("windows-{}", &other[3..]).as_bytes());
+            }
+            return Encoding::for_label(name.as_bytes());
+        }
+    };
+    return Encoding::for_label(label.as_bytes());
+}
+
+/// the first non-utf-8 encoding declared via `SET client_encoding` or
+/// `\encoding` in a document
+fn declared_encoding(bytes: &[u8]) -> Option<&'static Encoding> {
+    return DECLARED_ENCODING
+        .captures_iter(bytes)
+        .filter_map(|captures| pg_encoding(&String::from_utf8_lossy(&captures[1])))
+        .find(|encoding| *encoding != UTF_8);
+}
+
+/// a document transcoded to utf-8
+pub struct DecodedDocument {
+    pub text: String,
+    /// the encoding of the original file
+    pub encoding: &'static Encoding,
+    /// the byte offset in the original file of each utf-8 byte offset in
+    /// `text`, plus one for the end of the text. `None` if the file was
+    /// already utf-8.
+    offsets: Option<Vec<usize>>,
+}
+
+impl DecodedDocument {
+    /// translate a byte offset in `text` into a byte offset in the original file
+    pub fn original_offset(&self, offset: usize) -> usize {
+        match &self.offsets {
+            Some(offsets) => return offsets[offset],
+            None => return offset,
+        }
+    }
+}
+
+/// transcode a file to utf-8. If no encoding is given, the file is assumed to
+/// be utf-8, then whatever it declares via `SET client_encoding` or
+/// `\encoding`, then LATIN1.
+pub fn decode(
+    bytes: Vec<u8>,
+    encoding: Option<&'static Encoding>,
+) -> Result<DecodedDocument, Failure> {
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => {
+            if std::str::from_utf8(&bytes).is_ok() {
+                UTF_8
+            } else {
+                declared_encoding(&bytes).unwrap_or(WINDOWS_1252)
+            }
+        }
+    };
+    if encoding == UTF_8 {
+        return match String::from_utf8(bytes) {
+            Ok(text) => Ok(DecodedDocument {
+                text,
+                encoding,
+                offsets: None,
+            }),
+            Err(e) => Err(Failure::Other(format!("{}", e))),
+        };
+    }
+    // decode a byte at a time to track where each char starts in the original
+    let mut decoder = encoding.new_decoder_without_bom_handling();
+    let mut text = String::with_capacity(bytes.len());
+    let mut offsets: Vec<usize> = Vec::with_capacity(bytes.len() + 1);
+    let mut char_start = 0usize;
+    for i in 0..bytes.len() {
+        let last = i + 1 == bytes.len();
+        text.reserve(decoder.max_utf8_buffer_length(1).unwrap_or(16));
+        let before = text.len();
+        let (_, _, _) = decoder.decode_to_string(&bytes[i..i + 1], &mut text, last);
+        if text.len() > before {
+            offsets.resize(text.len(), char_start);
+            char_start = i + 1;
+        }
+    }
+    offsets.push(bytes.len());
+    return Ok(DecodedDocument {
+        text,
+        encoding,
+        offsets: Some(offsets),
+    });
+}
@@ -0,0 +1,211 @@
+use rusqlite::{types::ValueRef, Connection};
+use std::io::Write;
+
+use crate::sqlite::{self, Column};
+use crate::Failure;
+
+/// the postgres type for a sqlite column. Every integer becomes a BIGINT, since
+/// ids are 64-bit hashes regardless of what schema.sql declares.
+fn pg_type(column: &Column) -> &'static str {
+    let decl_type = column.decl_type.to_uppercase();
+    if decl_type.contains("INT") {
+        return "BIGINT";
+    } else if decl_type == "BOOLEAN" {
+        return "BOOLEAN";
+    } else if decl_type == "BLOB" {
+        return "BYTEA";
+    } else if decl_type.contains("REAL") || decl_type.contains("FLOA") || decl_type.contains("DOUB")
+    {
+        return "DOUBLE PRECISION";
+    } else {
+        return "TEXT";
+    }
+}
+
+/// escape a value for `COPY ... FROM stdin` in postgres' text format
+fn escape_copy_text(text: &str) -> Result<String, Failure> {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            '\0' => {
+                return Err(Failure::Other(
+                    "postgres text can't contain NUL".to_string(),
+                ))
+            }
+            c => escaped.push(c),
+        }
+    }
+    return Ok(escaped);
+}
+
+fn copy_value(column: &Column, value: ValueRef) -> Result<String, Failure> {
+    match value {
+        ValueRef::Null => return Ok("\\N".to_string()),
+        ValueRef::Integer(i) => {
+            if pg_type(column) == "BOOLEAN" {
+                return Ok(if i == 0 { "f" } else { "t" }.to_string());
+            }
+            return Ok(i.to_string());
+        }
+        ValueRef::Real(f) => return Ok(f.to_string()),
+        ValueRef::Text(bytes) => {
+            let text = std::str::from_utf8(bytes)
+                .map_err(|e| Failure::Other(format!("{}: {}", column.name, e)))?;
+            return escape_copy_text(text);
+        }
+        ValueRef::Blob(bytes) => {
+            let mut hex = String::with_capacity(3 + 2 * bytes.len());
+            // the bytea hex format, `\x...`, with its backslash escaped for COPY
+            hex.push_str("\\\\x");
+            for byte in bytes {
+                hex.push_str(format!("{:02x}", byte).as_str());
+            }
+            return Ok(hex);
+        }
+    }
+}
+
+/// write a script that `psql -f` can use to load a corpus db into postgres: a
+/// `CREATE TABLE` for every table, followed by a `COPY ... FROM stdin` block of
+/// its rows. Only primary keys are kept; foreign keys and indices are left out
+/// so that the load doesn't depend on the order of the tables.
+pub fn write_pg_copy<W: Write>(conn: &Connection, out: &mut W) -> Result<(), Failure> {
+    writeln!(out, "SET client_encoding = 'UTF8';")?;
+    writeln!(out, "BEGIN;")?;
+    for table in sqlite::tables(conn)? {
+        let columns = sqlite::columns(conn, table.as_str())?;
+        let mut definitions: Vec<String> = columns
+            .iter()
+            .map(|column| {
+                let mut definition = format!("\"{}\" {}", column.name, pg_type(column));
+                if column.not_null {
+                    definition.push_str(" NOT NULL");
+                }
+                return definition;
+            })
+            .collect();
+        let mut pk: Vec<&Column> = columns.iter().filter(|column| column.pk > 0).collect();
+        pk.sort_by_key(|column| column.pk);
+        if !pk.is_empty() {
+            let pk: Vec<String> = pk
+                .iter()
+                .map(|column| format!("\"{}\"", column.name))
+                .collect();
+            definitions.push(format!("PRIMARY KEY ({})", pk.join(", ")));
+        }
+        writeln!(
+            out,
+            "\nCREATE TABLE \"{}\" (\n    {}\n);",
+            table,
+            definitions.join("\n  , ")
+        )?;
+
+        let names: Vec<String> = columns
+            .iter()
+            .map(|column| format!("\"{}\"", column.name))
+            .collect();
+        let names = names.join(", ");
+        writeln!(out, "COPY \"{}\" ({}) FROM stdin;", table, names)?;
+        let mut query = conn.prepare(format!("SELECT {} FROM \"{}\"", names, table).as_str())?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            let mut values: Vec<String> = Vec::with_capacity(columns.len());
+            for (i, column) in columns.iter().enumerate() {
+                values.push(copy_value(column, row.get_ref(i)?)?);
+            }
+            writeln!(out, "{}", values.join("\t"))?;
+        }
+        writeln!(out, "\\.")?;
+    }
+    writeln!(out, "\nCOMMIT;")?;
+    out.flush()?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// split a COPY text-format line into its values the way postgres does,
+    /// with `None` for `\N`
+    fn parse_copy_line(line: &str) -> Vec<Option<String>> {
+        return line
+            .split('\t')
+            .map(|field| {
+                if field == "\\N" {
+                    return None;
+                }
+                let mut value = String::with_capacity(field.len());
+                let mut chars = field.chars();
+                while let Some(c) = chars.next() {
+                    if c != '\\' {
+                        value.push(c);
+                        continue;
+                    }
+                    match chars.next() {
+                        Some('n') => value.push('\n'),
+                        Some('r') => value.push('\r'),
+                        Some('t') => value.push('\t'),
+                        Some(c) => value.push(c),
+                        None => panic!("trailing backslash in {:?}", field),
+                    }
+                }
+                return Some(value);
+            })
+            .collect();
+    }
+
+    /// the COPY rows of `table` in `write_pg_copy`'s output
+    fn copied_rows(script: &str, table: &str) -> Vec<Vec<Option<String>>> {
+        let start = format!("COPY \"{}\" ", table);
+        return script
+            .lines()
+            .skip_while(|line| !line.starts_with(start.as_str()))
+            .skip(1)
+            .take_while(|line| *line != "\\.")
+            .map(parse_copy_line)
+            .collect();
+    }
+
+    #[test]
+    fn escapes_round_trip() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch("CREATE TABLE t(id INTEGER PRIMARY KEY, \"text\" TEXT);")
+            .unwrap();
+        let texts = [
+            Some("a\tb"),
+            Some("a\nb"),
+            Some("a\\b"),
+            Some("\\N"),
+            Some("a\r\n\\t"),
+            None,
+        ];
+        for (id, text) in texts.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO t(id, \"text\") VALUES (?, ?)",
+                rusqlite::params![id as i64, text],
+            )
+            .unwrap();
+        }
+        let mut script = Vec::<u8>::new();
+        write_pg_copy(&conn, &mut script).unwrap();
+        let script = String::from_utf8(script).unwrap();
+        let rows = copied_rows(script.as_str(), "t");
+        assert_eq!(rows.len(), texts.len());
+        for (id, (row, text)) in rows.iter().zip(texts.iter()).enumerate() {
+            assert_eq!(
+                row,
+                &vec![Some(id.to_string()), text.map(|text| text.to_string())]
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_nul() {
+        assert!(escape_copy_text("a\0b").is_err());
+    }
+}
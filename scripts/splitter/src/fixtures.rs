@@ -0,0 +1,163 @@
+use rusqlite::Connection;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use url::Url;
+
+use crate::{Failure, PG_FAMILY};
+
+/// what a parser should make of a fixture's `input.sql`
+#[derive(Serialize)]
+struct Expected {
+    /// the statement id
+    id: i64,
+    /// language hints as in the `languages` table
+    languages: Vec<String>,
+    fingerprint: Option<i64>,
+    predictions: Vec<Prediction>,
+}
+
+#[derive(Serialize)]
+struct Prediction {
+    oracle: String,
+    language: String,
+    valid: Option<bool>,
+    error: Option<String>,
+}
+
+/// the fixtures written for one version
+pub struct ExportedVersion {
+    pub version: String,
+    pub files: usize,
+    pub fixtures: usize,
+}
+
+/// the file stem of the first url that ends in a file name, e.g. `join` for
+/// `https://github.com/postgres/postgres/blob/REL_14_STABLE/src/test/regress/sql/join.sql#L1-L2`
+fn file_stem(urls: &[String]) -> Option<String> {
+    for url in urls {
+        let parsed = match Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(_) => continue,
+        };
+        let segment = parsed
+            .path_segments()
+            .and_then(|segments| segments.filter(|s| s.contains('.')).last());
+        if let Some(segment) = segment {
+            if let Some(stem) = Path::new(segment).file_stem() {
+                return Some(stem.to_string_lossy().into_owned());
+            }
+        }
+    }
+    return None;
+}
+
+fn write_pretty_json<T: Serialize>(path: &Path, value: &T) -> Result<(), Failure> {
+    let json = serde_json::to_vec_pretty(value).map_err(io::Error::from)?;
+    fs::write(path, json)?;
+    return Ok(());
+}
+
+/// write a regression-style test suite for each postgres version in a corpus
+/// db: `versions/NNN/regress/<file>/NNNN/input.sql` and `expected.json` for
+/// each statement in each source file, in order. A document belongs to the
+/// versions it was ingested for with `--pg-version`.
+pub fn export_fixtures(conn: &Connection, out_dir: &Path) -> Result<Vec<ExportedVersion>, Failure> {
+    let versions: Vec<(i64, String)> = {
+        let mut query =
+            conn.prepare("SELECT id, version FROM versions WHERE family = ? ORDER BY version")?;
+        let rows = query.query_map([PG_FAMILY], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        rows.collect::<Result<Vec<(i64, String)>, rusqlite::Error>>()?
+    };
+    let mut documents_in_version = conn.prepare(
+        "SELECT document_id FROM document_versions WHERE version_id = ? ORDER BY document_id",
+    )?;
+    let mut document_urls = conn.prepare(
+        "SELECT urls.url FROM document_urls JOIN urls ON urls.id = document_urls.url_id
+        WHERE document_urls.document_id = ? ORDER BY urls.url",
+    )?;
+    // PL bodies come right after the statement containing them
+    let mut document_statements = conn.prepare(
+        "SELECT s.id, s.text FROM document_statements AS ds
+        JOIN statements AS s ON s.id = ds.statement_id
+        WHERE ds.document_id = ? ORDER BY ds.start_offset, ds.end_offset DESC",
+    )?;
+    let mut statement_languages = conn.prepare(
+        "SELECT l.name FROM statement_languages AS sl JOIN languages AS l ON l.id = sl.language_id
+        WHERE sl.statement_id = ? ORDER BY l.id",
+    )?;
+    let mut statement_fingerprint =
+        conn.prepare("SELECT fingerprint FROM statement_fingerprints WHERE statement_id = ?")?;
+    let mut statement_predictions = conn.prepare(
+        "SELECT o.name, l.name, p.valid, p.error FROM predictions AS p
+        JOIN oracles AS o ON o.id = p.oracle_id
+        JOIN languages AS l ON l.id = p.language_id
+        WHERE p.statement_id = ? ORDER BY o.name, l.name",
+    )?;
+
+    let mut exported: Vec<ExportedVersion> = Vec::with_capacity(versions.len());
+    for (version_id, version) in versions {
+        let version_dir = match version.parse::<u32>() {
+            Ok(major) => format!("{:03}", major),
+            Err(_) => version.clone(),
+        };
+        let version_dir = out_dir.join("versions").join(version_dir).join("regress");
+        let mut result = ExportedVersion {
+            version,
+            files: 0,
+            fixtures: 0,
+        };
+        let document_ids: Vec<i64> = documents_in_version
+            .query_map([version_id], |row| row.get(0))?
+            .collect::<Result<Vec<i64>, rusqlite::Error>>()?;
+        let mut names: HashSet<String> = HashSet::new();
+        for document_id in document_ids {
+            let urls: Vec<String> = document_urls
+                .query_map([document_id], |row| row.get(0))?
+                .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+            let mut name = file_stem(urls.as_slice()).unwrap_or(format!("{:x}", document_id));
+            if names.contains(&name) {
+                name = format!("{}-{:x}", name, document_id);
+            }
+            names.insert(name.clone());
+            let statements: Vec<(i64, String)> = document_statements
+                .query_map([document_id], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<Vec<(i64, String)>, rusqlite::Error>>()?;
+            for (i, (id, text)) in statements.into_iter().enumerate() {
+                let fixture_dir = version_dir.join(&name).join(format!("{:04}", i));
+                fs::create_dir_all(&fixture_dir)?;
+                fs::write(fixture_dir.join("input.sql"), text)?;
+                let languages = statement_languages
+                    .query_map([id], |row| row.get(0))?
+                    .collect::<Result<Vec<String>, rusqlite::Error>>()?;
+                let fingerprint: Option<i64> = statement_fingerprint
+                    .query_map([id], |row| row.get(0))?
+                    .next()
+                    .transpose()?;
+                let predictions = statement_predictions
+                    .query_map([id], |row| {
+                        Ok(Prediction {
+                            oracle: row.get(0)?,
+                            language: row.get(1)?,
+                            valid: row.get(2)?,
+                            error: row.get(3)?,
+                        })
+                    })?
+                    .collect::<Result<Vec<Prediction>, rusqlite::Error>>()?;
+                let expected = Expected {
+                    id,
+                    languages,
+                    fingerprint,
+                    predictions,
+                };
+                write_pretty_json(fixture_dir.join("expected.json").as_path(), &expected)?;
+                result.fixtures += 1;
+            }
+            result.files += 1;
+        }
+        exported.push(result);
+    }
+    return Ok(exported);
+}
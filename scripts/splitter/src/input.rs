@@ -0,0 +1,235 @@
+use flate2::read::GzDecoder;
+use glob::Pattern;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::Failure;
+
+/// a single file's worth of input, read from stdin, a file, a directory, or a
+/// tarball.
+pub struct InputDocument {
+    /// the path relative to the input root, e.g. `src/test/regress/sql/join.sql`.
+    /// For a single file this is the file's name; for stdin it's empty.
+    pub path: String,
+    pub bytes: Vec<u8>,
+}
+
+pub fn is_tarball<P: AsRef<Path>>(path: P) -> bool {
+    let name = path.as_ref().to_string_lossy();
+    return name.ends_with(".tar") || name.ends_with(".tar.gz") || name.ends_with(".tgz");
+}
+
+/// the documents read from an input, each read only when it's reached
+pub struct InputDocuments {
+    /// the number of documents
+    pub len: usize,
+    documents: Box<dyn Iterator<Item = Result<InputDocument, Failure>>>,
+}
+
+impl Iterator for InputDocuments {
+    type Item = Result<InputDocument, Failure>;
+    fn next(&mut self) -> Option<Self::Item> {
+        return self.documents.next();
+    }
+}
+
+impl InputDocuments {
+    fn from_vec(documents: Vec<InputDocument>) -> Self {
+        return InputDocuments {
+            len: documents.len(),
+            documents: Box::new(documents.into_iter().map(Ok)),
+        };
+    }
+}
+
+/// list the documents matching `pattern` in `input`. A directory's documents
+/// are read in order of their paths, and a tarball's in the order they were
+/// archived.
+pub fn read_input(input: &str, pattern: &Pattern) -> Result<InputDocuments, Failure> {
+    if input == "stdin" {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        return Ok(InputDocuments::from_vec(vec![InputDocument {
+            path: String::new(),
+            bytes,
+        }]));
+    }
+    let root = PathBuf::from(input);
+    if root.is_dir() {
+        return read_dir(root.as_path(), pattern);
+    } else if is_tarball(&root) {
+        return read_tarball(root.as_path(), pattern);
+    }
+    // a lone file is read regardless of the pattern
+    let path = match root.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => input.to_string(),
+    };
+    return Ok(InputDocuments::from_vec(vec![InputDocument {
+        path,
+        bytes: fs::read(root)?,
+    }]));
+}
+
+fn read_dir(root: &Path, pattern: &Pattern) -> Result<InputDocuments, Failure> {
+    let mut paths: Vec<(String, PathBuf)> = vec![];
+    let mut todo = vec![root.to_path_buf()];
+    while let Some(dir) = todo.pop() {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let path = entry.path();
+            // file_type doesn't follow symlinks, so symlink cycles aren't walked
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                todo.push(path);
+                continue;
+            }
+            if file_type.is_symlink() && path.is_dir() {
+                continue;
+            }
+            let relative_path = path.strip_prefix(root).unwrap().to_string_lossy();
+            if !pattern.matches(&relative_path) {
+                continue;
+            }
+            paths.push((relative_path.into_owned(), path));
+        }
+    }
+    paths.sort();
+    return Ok(InputDocuments {
+        len: paths.len(),
+        documents: Box::new(paths.into_iter().map(|(relative_path, path)| {
+            return Ok(InputDocument {
+                path: relative_path,
+                bytes: fs::read(&path)?,
+            });
+        })),
+    });
+}
+
+fn open_tarball(path: &Path) -> Result<tar::Archive<Box<dyn Read + Send>>, Failure> {
+    let file = File::open(path)?;
+    let reader: Box<dyn Read + Send> = if path.to_string_lossy().ends_with(".tar") {
+        Box::new(file)
+    } else {
+        Box::new(GzDecoder::new(file))
+    };
+    return Ok(tar::Archive::new(reader));
+}
+
+/// a path without `.` or `..` components
+fn normal_components(path: &Path) -> PathBuf {
+    return path
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect();
+}
+
+/// the directory every path is in, e.g. `postgres-REL_14_STABLE`, if any
+fn common_root(paths: &[PathBuf]) -> Option<PathBuf> {
+    let root: PathBuf = paths.first()?.components().next()?.as_os_str().into();
+    let shared = paths
+        .iter()
+        .all(|path| path.starts_with(&root) && path.components().count() > 1);
+    return if shared { Some(root) } else { None };
+}
+
+/// an entry's path relative to the root of the source tree
+fn relative_path(entry_path: &Path, root: Option<&Path>) -> String {
+    let path = normal_components(entry_path);
+    let path = match root {
+        Some(root) => path.strip_prefix(root).unwrap_or(&path),
+        None => &path,
+    };
+    return path.to_string_lossy().into_owned();
+}
+
+/// list matching files in a `.tar` or `.tar.gz`, then read them on another
+/// thread as they're needed. If every file is in one directory, e.g.
+/// `postgres-REL_14_STABLE/`, that directory is stripped so that paths are
+/// relative to the root of the source tree.
+fn read_tarball(path: &Path, pattern: &Pattern) -> Result<InputDocuments, Failure> {
+    let mut entry_paths: Vec<PathBuf> = vec![];
+    for entry in open_tarball(path)?.entries()? {
+        let entry = entry?;
+        if entry.header().entry_type().is_file() {
+            entry_paths.push(normal_components(entry.path()?.as_ref()));
+        }
+    }
+    let root = common_root(entry_paths.as_slice());
+    let len = entry_paths
+        .iter()
+        .filter(|entry_path| pattern.matches(&relative_path(entry_path, root.as_deref())))
+        .count();
+
+    // entries borrow their archive, so the archive stays on the reading thread
+    let (send, recv) = mpsc::sync_channel::<Result<InputDocument, Failure>>(1);
+    let (path, pattern) = (path.to_path_buf(), pattern.clone());
+    thread::spawn(move || {
+        let read = || -> Result<(), Failure> {
+            let mut archive = open_tarball(path.as_path())?;
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                if !entry.header().entry_type().is_file() {
+                    continue;
+                }
+                let relative_path = relative_path(entry.path()?.as_ref(), root.as_deref());
+                if !pattern.matches(&relative_path) {
+                    continue;
+                }
+                let mut bytes = Vec::with_capacity(entry.header().size().unwrap_or(0) as usize);
+                entry.read_to_end(&mut bytes)?;
+                let document = InputDocument {
+                    path: relative_path,
+                    bytes,
+                };
+                if send.send(Ok(document)).is_err() {
+                    return Ok(()); // nothing more is wanted
+                }
+            }
+            return Ok(());
+        };
+        if let Err(e) = read() {
+            let _ = send.send(Err(e));
+        }
+    });
+    return Ok(InputDocuments {
+        len,
+        documents: Box::new(recv.into_iter()),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        return paths
+            .iter()
+            .map(|path| normal_components(Path::new(path)))
+            .collect();
+    }
+
+    #[test]
+    fn strips_a_shared_top_level_directory() {
+        let entries = paths(&["./pg/src/a.sql", "pg/b.sql"]);
+        let root = common_root(entries.as_slice());
+        assert_eq!(root, Some(PathBuf::from("pg")));
+        let relative: Vec<String> = entries
+            .iter()
+            .map(|entry| relative_path(entry, root.as_deref()))
+            .collect();
+        assert_eq!(relative, vec!["src/a.sql", "b.sql"]);
+    }
+
+    #[test]
+    fn keeps_top_level_directories_that_arent_shared() {
+        assert_eq!(common_root(paths(&["a/x.sql", "b/y.sql"]).as_slice()), None);
+        assert_eq!(common_root(paths(&["a/x.sql", "a.sql"]).as_slice()), None);
+        assert_eq!(common_root(paths(&["x.sql"]).as_slice()), None);
+        assert_eq!(common_root(&[]), None);
+        assert_eq!(relative_path(Path::new("./a/x.sql"), None), "a/x.sql");
+    }
+}
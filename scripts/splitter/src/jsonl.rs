@@ -0,0 +1,134 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+use crate::{
+    span::LineSpan,
+    writer::{CorpusWriter, DocumentRows},
+    Document, Failure, Language, Statement, StatementSource,
+};
+
+/// one line of output: a statement and everywhere it appears in one document
+#[derive(Serialize)]
+struct StatementRecord<'a> {
+    id: i64,
+    document_id: i64,
+    path: &'a str,
+    text: &'a str,
+    /// language names as in the `languages` table
+    languages: Vec<&'static str>,
+    fingerprint: Option<i64>,
+    /// the urls at which the document may be found
+    urls: &'a [String],
+    sources: Vec<SourceRecord<'a>>,
+}
+
+#[derive(Serialize)]
+struct SourceRecord<'a> {
+    locator: Option<&'a str>,
+    #[serde(flatten)]
+    span: &'a LineSpan,
+}
+
+/// writes one json object per line for each distinct statement in each
+/// document, in the order the statements were split. Documents aren't
+/// deduplicated.
+pub struct JsonlWriter<W: Write> {
+    out: W,
+    current: Option<DocumentRows>,
+}
+
+impl<W: Write> JsonlWriter<W> {
+    pub fn new(out: W) -> Self {
+        return JsonlWriter { out, current: None };
+    }
+
+    fn current(&mut self) -> &mut DocumentRows {
+        return self
+            .current
+            .as_mut()
+            .expect("write_* called outside of a document");
+    }
+}
+
+impl<W: Write> CorpusWriter for JsonlWriter<W> {
+    fn begin_document(&mut self, doc: &Document, urls: &[String]) -> Result<bool, Failure> {
+        self.current = Some(DocumentRows::new(doc, urls));
+        return Ok(true);
+    }
+    fn write_statements(&mut self, statements: &[Statement]) -> Result<(), Failure> {
+        self.current().statements.extend_from_slice(statements);
+        return Ok(());
+    }
+    fn write_sources(&mut self, sources: &[StatementSource]) -> Result<(), Failure> {
+        self.current().sources.extend_from_slice(sources);
+        return Ok(());
+    }
+    fn write_fingerprints(&mut self, fingerprints: &[(i64, i64)]) -> Result<(), Failure> {
+        self.current().fingerprints.extend_from_slice(fingerprints);
+        return Ok(());
+    }
+    fn write_languages(&mut self, languages: &[(i64, Language)]) -> Result<(), Failure> {
+        self.current().languages.extend_from_slice(languages);
+        return Ok(());
+    }
+    fn end_document(&mut self) -> Result<(), Failure> {
+        if let Some(rows) = self.current.take() {
+            write_rows(&mut self.out, &rows)?;
+        }
+        return Ok(());
+    }
+    fn abort_document(&mut self) -> Result<(), Failure> {
+        self.current = None;
+        return Ok(());
+    }
+    fn commit(&mut self) -> Result<(), Failure> {
+        self.out.flush()?;
+        return Ok(());
+    }
+}
+
+fn write_rows<W: Write>(out: &mut W, rows: &DocumentRows) -> Result<(), Failure> {
+    let mut languages: HashMap<i64, Vec<&'static str>> = HashMap::new();
+    for (id, language) in rows.languages.iter() {
+        let names = languages.entry(*id).or_insert_with(Vec::new);
+        if !names.contains(&language.name()) {
+            names.push(language.name());
+        }
+    }
+    let fingerprints: HashMap<i64, i64> = rows.fingerprints.iter().cloned().collect();
+    let mut sources: HashMap<i64, Vec<&StatementSource>> = HashMap::new();
+    for src in rows.sources.iter() {
+        sources
+            .entry(src.statement_id())
+            .or_insert_with(Vec::new)
+            .push(src);
+    }
+    let mut written: HashSet<i64> = HashSet::new();
+    for statement in rows.statements.iter() {
+        if !written.insert(statement.id()) {
+            continue;
+        }
+        let record = StatementRecord {
+            id: statement.id(),
+            document_id: rows.id,
+            path: rows.path.as_str(),
+            text: statement.text(),
+            languages: languages.remove(&statement.id()).unwrap_or_default(),
+            fingerprint: fingerprints.get(&statement.id()).cloned(),
+            urls: rows.urls.as_slice(),
+            sources: sources
+                .remove(&statement.id())
+                .unwrap_or_default()
+                .into_iter()
+                .map(|src| SourceRecord {
+                    locator: src.locator(),
+                    span: src.span(),
+                })
+                .collect(),
+        };
+        serde_json::to_writer(&mut *out, &record).map_err(io::Error::from)?;
+        out.write_all(b"\n")?;
+    }
+    return Ok(());
+}
@@ -0,0 +1,1108 @@
+//! split psql documents into statements, classify them, and record them in a
+//! corpus database. The `splitter` binary is a thin wrapper around `Corpus` and
+//! `Splitter`.
+use encoding_rs::Encoding;
+use lazy_static::lazy_static;
+use pg_query_wrapper as pg_query;
+use prost::Message;
+use psql_splitter;
+use regex::Regex;
+use rusqlite::Connection;
+use std::{fmt, io};
+use xxhash_rust::xxh3::xxh3_64;
+use xxhash_rust::xxh64::xxh64;
+pub mod encoding;
+pub mod export;
+pub mod fixtures;
+pub mod input;
+pub mod jsonl;
+pub mod minimize;
+pub mod progress;
+pub mod span;
+pub mod sqlite;
+pub mod url_template;
+pub mod writer;
+use encoding::DecodedDocument;
+use input::InputDocument;
+use span::{Cursor, LineSpan};
+use url_template::UrlTemplate;
+use writer::CorpusWriter;
+
+/// the `versions.family` recorded for `--pg-version`
+pub const PG_FAMILY: &str = "postgres";
+
+/// this build's version, as recorded in `runs.splitter_version`
+pub const SPLITTER_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// the last commit to the splitter's source, or "unknown" if it wasn't built
+/// from a git checkout
+pub const SPLITTER_COMMIT: &str = env!("SPLITTER_COMMIT");
+/// the locked commit of psql_splitter, which decides where statements end
+pub const PSQL_SPLITTER_COMMIT: &str = env!("PSQL_SPLITTER_COMMIT");
+/// the locked commit of pg_query_wrapper, which bundles libpg_query
+pub const PG_QUERY_WRAPPER_COMMIT: &str = env!("PG_QUERY_WRAPPER_COMMIT");
+
+#[derive(Debug)]
+pub enum Failure {
+    IoErr(io::Error),
+    PgQueryError(pg_query::Failure),
+    DirDne,
+    NotDir,
+    Sqlite(rusqlite::Error),
+    /// two different statement texts share an xxhash3_64 id
+    HashCollision {
+        id: i64,
+        existing: String,
+        new: String,
+    },
+    /// a corpus db's schema version isn't one this build can write to
+    SchemaVersion {
+        found: (u32, u32),
+        wanted: (u32, u32),
+    },
+    /// `psql_splitter` couldn't consume a document past this point
+    Split {
+        /// the utf-8 byte offset of the remainder within the document
+        offset: usize,
+        /// the 1-indexed line on which the remainder starts
+        line: usize,
+        remainder_preview: String,
+    },
+    Other(String),
+}
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::IoErr(e) => write!(f, "{}", e),
+            Failure::PgQueryError(e) => write!(f, "{:?}", e),
+            Failure::DirDne => write!(f, "directory does not exist"),
+            Failure::NotDir => write!(f, "not a directory"),
+            Failure::Sqlite(e) => write!(f, "{}", e),
+            Failure::HashCollision { id, existing, new } => write!(
+                f,
+                "statement id {:x} is the xxhash3_64 of both {:?} and {:?}",
+                id, existing, new
+            ),
+            Failure::SchemaVersion { found, wanted } => {
+                write!(
+                    f,
+                    "unsupported schema version {}.{}; wanted {}.{}",
+                    found.0, found.1, wanted.0, wanted.1
+                )?;
+                if found < wanted {
+                    write!(f, " (run `splitter migrate` to upgrade)")?;
+                }
+                return Ok(());
+            }
+            Failure::Split {
+                line,
+                remainder_preview,
+                ..
+            } => write!(
+                f,
+                "unable to split past line {}: {:?}",
+                line, remainder_preview
+            ),
+            Failure::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+impl std::error::Error for Failure {}
+impl From<io::Error> for Failure {
+    fn from(e: io::Error) -> Self {
+        Self::IoErr(e)
+    }
+}
+impl From<pg_query::Failure> for Failure {
+    fn from(e: pg_query::Failure) -> Self {
+        Self::PgQueryError(e)
+    }
+}
+impl From<rusqlite::Error> for Failure {
+    fn from(e: rusqlite::Error) -> Self {
+        Failure::Sqlite(e)
+    }
+}
+#[derive(Clone, Debug)]
+pub struct Statement {
+    text: String,
+    /// the xxhash3_64 of the overall utf-8 document that this statement is
+    /// drawn from
+    document_id: i64,
+    /// the xxhash3_64 of the text
+    id: i64,
+    /// might include line numbers inside a collection
+    language: Language,
+    // urls: Vec<String>,
+    // start_line: usize,
+    n_lines: usize,
+}
+
+impl Statement {
+    pub fn new(text: String, language: Language, document_id: i64) -> Self {
+        let n_lines = text.matches("\n").count();
+        Statement {
+            id: xxh3_64(text.as_bytes()) as i64,
+            document_id,
+            text,
+            language,
+            n_lines,
+        }
+    }
+    /// a statement as split from a document: `Psql` if it's a psql
+    /// meta-command, else `PgSql`
+    pub fn classify(text: &str, document_id: i64) -> Self {
+        return text_to_statement(text, document_id);
+    }
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+    pub fn language(&self) -> Language {
+        self.language
+    }
+    pub fn document_id(&self) -> i64 {
+        self.document_id
+    }
+    /// the number of newlines in the text
+    pub fn n_lines(&self) -> usize {
+        self.n_lines
+    }
+    pub fn fingerprint(self: &Self) -> Result<i64, Failure> {
+        let (fingerprint, _) = pg_query::fingerprint(self.text.clone().as_str())?;
+        return Ok(fingerprint as i64);
+    }
+    /// the text with constants replaced by `$n` placeholders
+    pub fn normalize(self: &Self) -> Result<String, Failure> {
+        return Ok(pg_query::normalize(self.text.as_str())?);
+    }
+    /// pg_query's parse tree of the text
+    pub fn ast(self: &Self) -> Result<StatementAst, Failure> {
+        let tree = pg_query::parse_to_protobuf(self.text.as_str())?;
+        let mut protobuf: Vec<u8> = Vec::with_capacity(tree.encoded_len());
+        tree.encode(&mut protobuf)
+            .map_err(|e| Failure::Other(e.to_string()))?;
+        return Ok(StatementAst {
+            statement_id: self.id,
+            pg_query_version: tree.version,
+            protobuf,
+        });
+    }
+    fn with_source(self: &Self, span: &LineSpan) -> StatementSource {
+        StatementSource {
+            statement_id: self.id,
+            document_id: self.document_id,
+            span: span.clone(),
+            locator: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StatementSource {
+    statement_id: i64,       //
+    span: LineSpan,          // where the statement is within the document
+    document_id: i64, // xxhash3_64 of the overall document from which this statement is drawn
+    locator: Option<String>, // a link to the statement's lines, expanded from a --url-template
+}
+impl StatementSource {
+    pub fn statement_id(&self) -> i64 {
+        self.statement_id
+    }
+    pub fn document_id(&self) -> i64 {
+        self.document_id
+    }
+    pub fn span(&self) -> &LineSpan {
+        &self.span
+    }
+    pub fn locator(&self) -> Option<&str> {
+        self.locator.as_deref()
+    }
+}
+
+fn extract_protobuf_string(node: &Box<pg_query::pbuf::Node>) -> Result<String, Failure> {
+    use pg_query::pbuf::node::Node;
+    match node.node.as_ref() {
+        Some(Node::String(s)) => return Ok(s.str.clone()),
+        _ => return Err(unexpected_pl_option(node)),
+    }
+}
+
+/// valid sql can still produce option shapes we don't expect, e.g. odd `AS`
+/// lists, so they fail the document rather than panicking
+fn unexpected_pl_option<T: std::fmt::Debug>(option: &T) -> Failure {
+    return Failure::Other(format!("unexpected pl option {:?}", option));
+}
+/// the language and body of a `DO` block or `CREATE FUNCTION` statement
+struct PlBlock {
+    language: String,
+    /// the body's text and pg_query's location of the `AS` option, a byte
+    /// offset at or before the body
+    body: Option<(String, usize)>,
+}
+
+fn parse_pl(nodes: &Vec<pg_query::pbuf::Node>) -> Result<PlBlock, Failure> {
+    use pg_query::pbuf::node::Node;
+
+    let mut body: Option<(String, usize)> = None;
+    let mut lang: String = "plpgsql".into();
+    for node in nodes {
+        if let Some(Node::DefElem(inner)) = node.node.as_ref() {
+            let location = if inner.location < 0 {
+                0
+            } else {
+                inner.location as usize
+            };
+            let arg = match inner.arg.as_ref() {
+                Some(arg) => arg,
+                None => return Err(unexpected_pl_option(inner)),
+            };
+            match inner.defname.as_str() {
+                "as" => match arg.node.as_ref() {
+                    Some(Node::String(s)) => body = Some((s.str.clone(), location)),
+                    Some(Node::List(l)) => {
+                        // for example, `LANGUAGE C AS 'MODULE_PATHNAME', 'fn'`
+                        // comes across as 2 items: an object file and a
+                        // symbol rather than a body
+                        if l.items.len() == 1 {
+                            let item = &l.items[0];
+                            match item.node.as_ref() {
+                                Some(Node::String(s)) => body = Some((s.str.clone(), location)),
+                                _ => return Err(unexpected_pl_option(item)),
+                            }
+                        }
+                    }
+                    _ => return Err(unexpected_pl_option(inner)),
+                },
+                "language" => lang = extract_protobuf_string(arg)?,
+                _ => {} // ignore
+            }
+        }
+    }
+    return Ok(PlBlock {
+        language: lang,
+        body,
+    });
+}
+fn parse_do_stmt(d: &pg_query::pbuf::DoStmt) -> Result<PlBlock, Failure> {
+    return parse_pl(d.args.as_ref());
+}
+fn parse_fn_stmt(f: &pg_query::pbuf::CreateFunctionStmt) -> Result<PlBlock, Failure> {
+    return parse_pl(f.options.as_ref());
+}
+
+// PL bodies often need their context to parse successfully, e.g.
+// `DO $$ BEGIN RETURN QUERY ... $$` isn't valid, so the outer statement is kept
+// alongside the extracted body. Statements pg_query can't parse, or that aren't
+// DO blocks or function definitions, have no PL block.
+fn extract_pl(input: &str) -> Result<Option<PlBlock>, Failure> {
+    use pg_query::pbuf::node::Node;
+    let stmts = match pg_query::parse_to_protobuf(input) {
+        Ok(tree) => tree.stmts,
+        Err(_) => return Ok(None),
+    };
+
+    // for some reason there's a section in partition_prune that doesn't get
+    // split when the entire document is passed via --input.  Passing the text
+    // via stdin, however, causes the correct splits.
+    // I'm ignoring it for now, since it only causes one snag in the entire regression
+    // test suite.
+    // if stmts.len() != 1 {
+    //     println!("--------------------------------------------------");
+    //     println!("{}", input);
+    //     println!("==================================================");
+    //     println!("{:?}", stmts);
+    // }
+
+    // sometimes there'll be empty 0-length statements, e.g. `/* empty query */;`
+    if stmts.len() == 0 {
+        return Ok(None); // empty stmt (comment-only?)
+    }
+    match stmts[0].stmt.as_ref().and_then(|node| node.node.as_ref()) {
+        Some(Node::DoStmt(stmt)) => return parse_do_stmt(stmt).map(Some),
+        Some(Node::CreateFunctionStmt(stmt)) => return parse_fn_stmt(stmt).map(Some),
+        _ => return Ok(None),
+    }
+}
+
+/// skip whitespace and `--` or (nested) `/* */` comments
+fn skip_whitespace_and_comments(mut rest: &str) -> &str {
+    loop {
+        rest = rest.trim_start();
+        if rest.starts_with("--") {
+            rest = match rest.find('\n') {
+                Some(end) => &rest[end..],
+                None => "",
+            };
+        } else if rest.starts_with("/*") {
+            let mut depth = 0;
+            let mut end = rest.len();
+            let bytes = rest.as_bytes();
+            let mut i = 0;
+            while i + 1 < bytes.len() {
+                match &bytes[i..i + 2] {
+                    b"/*" => depth += 1,
+                    b"*/" => depth -= 1,
+                    _ => {
+                        i += 1;
+                        continue;
+                    }
+                }
+                i += 2;
+                if depth == 0 {
+                    end = i;
+                    break;
+                }
+            }
+            rest = &rest[end..];
+        } else {
+            return rest;
+        }
+    }
+}
+
+/// the byte offset of a PL body within its statement's text: `location` is
+/// pg_query's location of the `AS` option, which is the `AS` keyword of a
+/// function definition or the string constant of a DO block. The body only
+/// has an offset if the constant spells it out verbatim, i.e. it's dollar-quoted
+/// or a plain quoted string without doubled quotes.
+fn pl_body_offset(text: &str, location: usize, body: &str) -> Option<usize> {
+    let mut rest = text.get(location..)?;
+    if rest
+        .get(..2)
+        .map_or(false, |keyword| keyword.eq_ignore_ascii_case("as"))
+    {
+        rest = &rest[2..];
+    }
+    rest = skip_whitespace_and_comments(rest);
+    let quote_len = if rest.starts_with('$') {
+        rest[1..].find('$')? + 2 // e.g. `$body$`
+    } else if rest.starts_with('\'') {
+        1
+    } else {
+        return None; // e.g. E'' or U&'' strings, whose escapes change the text
+    };
+    let offset = text.len() - rest.len() + quote_len;
+    if text.get(offset..offset + body.len()) == Some(body) {
+        return Some(offset);
+    }
+    return None;
+}
+
+/// a PL body extracted from a `DO` block or `CREATE FUNCTION` statement
+#[derive(Clone, Debug)]
+pub struct StatementChild {
+    parent_id: i64,
+    child_id: i64,
+    /// the byte offset of the body within the parent's text, if the body
+    /// appears verbatim (i.e. it isn't a quoted string with escapes)
+    start_offset: Option<usize>,
+    end_offset: Option<usize>,
+}
+impl StatementChild {
+    pub fn parent_id(&self) -> i64 {
+        self.parent_id
+    }
+    pub fn child_id(&self) -> i64 {
+        self.child_id
+    }
+    pub fn start_offset(&self) -> Option<usize> {
+        self.start_offset
+    }
+    pub fn end_offset(&self) -> Option<usize> {
+        self.end_offset
+    }
+}
+
+// psql stuff ---------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(i64)]
+pub enum Language {
+    PgSql = 0,
+    PlPgSql = 1,
+    Psql = 2,
+    PlPerl = 3,
+    PlTcl = 4,
+    PlPython2 = 5,
+    PlPython3 = 6,
+    Other = -1,
+}
+impl Language {
+    /// the `languages.id`
+    pub fn id(&self) -> i64 {
+        *self as i64
+    }
+    pub fn from_id(id: i64) -> Option<Language> {
+        use Language::*;
+        return [
+            PgSql, PlPgSql, Psql, PlPerl, PlTcl, PlPython2, PlPython3, Other,
+        ]
+        .iter()
+        .find(|language| language.id() == id)
+        .cloned();
+    }
+    /// the `languages.name`
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::PgSql => "pgsql",
+            Language::PlPgSql => "plpgsql",
+            Language::Psql => "psql",
+            Language::PlPerl => "plperl",
+            Language::PlTcl => "pltcl",
+            Language::PlPython2 => "plpython2",
+            Language::PlPython3 => "plpython3",
+            Language::Other => "other",
+        }
+    }
+}
+
+lazy_static! {
+    static ref PLPGSQL_NAME: Regex = Regex::new("(?i)^plpgsql$").unwrap();
+    static ref PLPERL_NAME: Regex = Regex::new("(?i)^plperl$").unwrap();
+    static ref PLTCL_NAME: Regex = Regex::new("(?i)^pltcl$").unwrap();
+    static ref PLPYTHON2_NAME: Regex = Regex::new("(?i)^plpython2?u$").unwrap();
+    static ref PLPYTHON3_NAME: Regex = Regex::new("(?i)^plpython3u$").unwrap();
+}
+
+fn identify_language(lang: &str) -> Language {
+    use Language::*;
+    if let Some(_) = PLPGSQL_NAME.find(lang) {
+        return PlPgSql;
+    } else if let Some(_) = PLPERL_NAME.find(lang) {
+        return PlPerl;
+    } else if let Some(_) = PLTCL_NAME.find(lang) {
+        return PlTcl;
+    } else if let Some(_) = PLPYTHON2_NAME.find(lang) {
+        return PlPython2;
+    } else if let Some(_) = PLPYTHON3_NAME.find(lang) {
+        return PlPython3;
+    } else {
+        return Other;
+    }
+}
+
+fn text_to_statement(text: &str, document_id: i64) -> Statement {
+    if psql_splitter::is_psql(text) {
+        return Statement::new(text.to_string(), Language::Psql, document_id);
+    } else {
+        return Statement::new(text.to_string(), Language::PgSql, document_id);
+    }
+}
+
+/// the language of a PL body. Unlike the language hint on the outer statement,
+/// `LANGUAGE sql` bodies are plain SQL.
+fn identify_body_language(lang: &str) -> Language {
+    if lang.eq_ignore_ascii_case("sql") {
+        return Language::PgSql;
+    }
+    return identify_language(lang);
+}
+
+/// tag a DO block or function definition with its language and extract its
+/// body, if the body is in a language we recognize, along with the body's byte
+/// offset within `s`
+fn recognize_pl_statement(
+    s: &Statement,
+) -> Result<Option<(Language, Option<(Statement, Option<usize>)>)>, Failure> {
+    if let Some(block) = extract_pl(s.text.as_str())? {
+        let language = identify_language(block.language.as_str());
+        let body = match block.body {
+            Some((body, location)) => match identify_body_language(block.language.as_str()) {
+                Language::Other => None,
+                body_language => {
+                    let offset = pl_body_offset(s.text.as_str(), location, body.as_str());
+                    Some((Statement::new(body, body_language, s.document_id), offset))
+                }
+            },
+            None => None,
+        };
+        return Ok(Some((language, body)));
+    } else {
+        return Ok(None);
+    }
+}
+
+/// how much of a failed split's remainder to keep for debugging
+const REMAINDER_PREVIEW_CHARS: usize = 80;
+
+/// split a document into statements. If `psql_splitter` can't consume the whole
+/// document, the statements split so far are returned along with a
+/// `Failure::Split` describing the remainder.
+fn split_psql_to_statements(input: &str) -> (Vec<String>, Option<Failure>) {
+    let mut statements: Vec<String> = vec![];
+    let mut rest = input;
+    while let Ok((r, text)) = psql_splitter::statement(rest) {
+        statements.push(text.to_string());
+        rest = r;
+    }
+    let consumed = &input[..input.len() - rest.len()];
+    debug_assert_eq!(consumed, statements.join("").as_str());
+    if rest.is_empty() {
+        return (statements, None);
+    }
+    let failure = Failure::Split {
+        offset: consumed.len(),
+        line: 1 + consumed.matches("\n").count(),
+        remainder_preview: rest.chars().take(REMAINDER_PREVIEW_CHARS).collect(),
+    };
+    return (statements, Some(failure));
+}
+
+// documents -------------------------------------------------------------------
+
+/// a document transcoded to utf-8 and identified, but not yet split
+pub struct Document {
+    path: String,
+    /// the xxhash3_64 of the utf-8 text
+    id: i64,
+    decoded: DecodedDocument,
+}
+
+impl Document {
+    pub fn new(path: String, decoded: DecodedDocument) -> Self {
+        Document {
+            id: xxh3_64(decoded.text.as_bytes()) as i64,
+            path,
+            decoded,
+        }
+    }
+    /// the path relative to the input root
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn id(&self) -> i64 {
+        self.id
+    }
+    pub fn text(&self) -> &str {
+        &self.decoded.text
+    }
+    /// the encoding of the original file
+    pub fn encoding(&self) -> &'static Encoding {
+        self.decoded.encoding
+    }
+    /// translate a byte offset in `text()` into a byte offset in the original file
+    pub fn original_offset(&self, offset: usize) -> usize {
+        self.decoded.original_offset(offset)
+    }
+}
+
+/// a statement's parse tree as a protobuf-encoded `pg_query.ParseResult`
+#[derive(Clone, Debug)]
+pub struct StatementAst {
+    pub statement_id: i64,
+    /// the `PG_VERSION_NUM` of the postgres parser in libpg_query, e.g. 130003
+    pub pg_query_version: i32,
+    pub protobuf: Vec<u8>,
+}
+
+lazy_static! {
+    /// the `PG_VERSION_NUM` of the postgres parser in libpg_query
+    pub(crate) static ref PG_QUERY_VERSION: i32 = pg_query::parse_to_protobuf("")
+        .map(|tree| tree.version)
+        .unwrap_or(0);
+    /// e.g. "13.3"
+    pub static ref LIBPG_QUERY_VERSION: String = format!(
+        "{}.{}",
+        *PG_QUERY_VERSION / 10000,
+        *PG_QUERY_VERSION % 10000
+    );
+    /// e.g. "pg_query (libpg_query 13.3)"
+    pub static ref PG_QUERY_ORACLE_NAME: String =
+        format!("pg_query (libpg_query {})", *LIBPG_QUERY_VERSION);
+    /// the xxhash64 of the name, as the go oracles derive their ids
+    pub static ref PG_QUERY_ORACLE_ID: i64 = xxh64(PG_QUERY_ORACLE_NAME.as_bytes(), 0) as i64;
+}
+
+/// pg_query's verdict on whether a statement is valid
+#[derive(Clone, Debug)]
+pub struct Prediction {
+    pub statement_id: i64,
+    pub oracle_id: i64,
+    pub language: Language,
+    pub valid: bool,
+    pub error: Option<String>,
+    /// libpg_query's 1-indexed char position of the error, if it reported one
+    pub cursorpos: Option<i32>,
+}
+
+impl Prediction {
+    fn from_fingerprint(statement: &Statement, result: &Result<i64, Failure>) -> Self {
+        let (error, cursorpos) = match result {
+            Ok(_) => (None, None),
+            // libpg_query reports a cursorpos of 0 when there's no position
+            Err(Failure::PgQueryError(e)) => (
+                Some(e.message.clone()),
+                Some(e.cursorpos).filter(|cursorpos| *cursorpos > 0),
+            ),
+            Err(e) => (Some(e.to_string()), None),
+        };
+        return Prediction {
+            statement_id: statement.id,
+            oracle_id: *PG_QUERY_ORACLE_ID,
+            language: Language::PgSql,
+            valid: result.is_ok(),
+            error,
+            cursorpos,
+        };
+    }
+}
+
+/// everything split out of a single document
+pub struct SplitDocument {
+    urls: Vec<String>,
+    statements: Vec<Statement>,
+    /// (statement_id, fingerprint)
+    fingerprints: Vec<(i64, i64)>,
+    /// empty unless `Splitter::store_ast`
+    asts: Vec<StatementAst>,
+    predictions: Vec<Prediction>,
+    /// (statement_id, normalized text)
+    normalized: Vec<(i64, String)>,
+    languages: Vec<(i64, Language)>,
+    sources: Vec<StatementSource>,
+    children: Vec<StatementChild>,
+    /// a `Failure::Split` if the document could only partially be split
+    split_failure: Option<Failure>,
+}
+
+impl SplitDocument {
+    /// the urls at which the document may be found
+    pub fn urls(&self) -> &[String] {
+        &self.urls
+    }
+    /// every statement in the order it appears, followed by any extracted PL bodies
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+    /// (statement_id, fingerprint) pairs for each `PgSql` statement that pg_query could parse
+    pub fn fingerprints(&self) -> &[(i64, i64)] {
+        &self.fingerprints
+    }
+    /// the parse trees of the `PgSql` statements that pg_query could parse, if
+    /// `Splitter::store_ast`
+    pub fn asts(&self) -> &[StatementAst] {
+        &self.asts
+    }
+    /// pg_query's prediction for each `PgSql` statement
+    pub fn predictions(&self) -> &[Prediction] {
+        &self.predictions
+    }
+    /// (statement_id, normalized text) pairs for each `PgSql` statement that
+    /// pg_query could parse
+    pub fn normalized(&self) -> &[(i64, String)] {
+        &self.normalized
+    }
+    /// (statement_id, language) hints
+    pub fn languages(&self) -> &[(i64, Language)] {
+        &self.languages
+    }
+    pub fn sources(&self) -> &[StatementSource] {
+        &self.sources
+    }
+    pub fn children(&self) -> &[StatementChild] {
+        &self.children
+    }
+    pub fn split_failure(&self) -> Option<&Failure> {
+        self.split_failure.as_ref()
+    }
+}
+
+// splitting -------------------------------------------------------------------
+
+/// splits documents into statements and classifies them
+#[derive(Clone, Debug, Default)]
+pub struct Splitter {
+    /// urls at which each document may be found. `{path}` is replaced with
+    /// each document's path relative to the input root.
+    pub urls: Vec<String>,
+    /// url templates at which each document may be found. The first
+    /// template's per-statement link is stored as each statement's locator.
+    pub templates: Vec<UrlTemplate>,
+    /// the git ref substituted for `{ref}` in each template
+    pub reference: String,
+    /// `None` to detect each document's encoding
+    pub encoding: Option<&'static Encoding>,
+    /// keep the unsplittable remainder of a document as a single statement in
+    /// the language `Other` rather than rejecting the document
+    pub best_effort: bool,
+    /// also record each statement's columns and char and utf-16 offsets
+    pub unicode_positions: bool,
+    /// also keep pg_query's parse tree of each `PgSql` statement
+    pub store_ast: bool,
+}
+
+impl Splitter {
+    /// transcode a file to utf-8
+    pub fn decode(&self, input: InputDocument) -> Result<Document, Failure> {
+        let InputDocument { path, bytes } = input;
+        let decoded = encoding::decode(bytes, self.encoding)?;
+        return Ok(Document::new(path, decoded));
+    }
+
+    /// the urls at which a document may be found, template-derived urls first
+    pub fn urls(&self, path: &str) -> Result<Vec<String>, Failure> {
+        let mut urls = Vec::with_capacity(self.templates.len() + self.urls.len());
+        for template in self.templates.iter() {
+            urls.push(
+                template
+                    .document_url(self.reference.as_str(), path)
+                    .map_err(Failure::Other)?,
+            );
+        }
+        for url in self.urls.iter() {
+            urls.push(url.replace("{path}", url_template::encode(path).as_str()));
+        }
+        return Ok(urls);
+    }
+
+    /// split a document into statements, classify them, and fingerprint the
+    /// `PgSql` ones
+    pub fn split(&self, doc: &Document) -> Result<SplitDocument, Failure> {
+        let urls = self.urls(doc.path())?;
+        let document_id = doc.id();
+        let (mut splits, split_failure) = split_psql_to_statements(doc.text());
+        let mut remainder_index: Option<usize> = None;
+        if let Some(failure) = &split_failure {
+            if !self.best_effort {
+                return Err(split_failure.unwrap());
+            }
+            if let Failure::Split { offset, .. } = failure {
+                remainder_index = Some(splits.len());
+                splits.push(doc.text()[*offset..].to_string());
+            }
+        }
+        let mut statements = Vec::<Statement>::with_capacity(splits.capacity());
+        let mut sources = Vec::<StatementSource>::with_capacity(splits.len());
+        let mut statement_fingerprints = Vec::<(i64, i64)>::with_capacity(splits.len());
+        let mut statement_asts = Vec::<StatementAst>::new();
+        let mut predictions = Vec::<Prediction>::with_capacity(splits.len());
+        let mut normalized = Vec::<(i64, String)>::with_capacity(splits.len());
+        let mut statement_children = Vec::<StatementChild>::new();
+        let mut pl_blocks = Vec::<(i64, Language)>::new();
+        let mut bodies = Vec::<Statement>::new();
+        let mut cursor = Cursor::start(self.unicode_positions);
+        for (i, split) in splits.into_iter().enumerate() {
+            let stmt = if remainder_index == Some(i) {
+                Statement::new(split, Language::Other, document_id)
+            } else {
+                text_to_statement(split.as_str(), document_id)
+            };
+            let (span, next) = LineSpan::new(&doc.decoded, cursor, stmt.text.as_str());
+            sources.push(stmt.with_source(&span));
+            if let Some((language, body)) = recognize_pl_statement(&stmt)? {
+                pl_blocks.push((stmt.id, language));
+                if let Some((body, body_offset)) = body {
+                    if let Some(body_offset) = body_offset {
+                        let body_start = cursor.after(&stmt.text[..body_offset]);
+                        let (body_span, _) =
+                            LineSpan::new(&doc.decoded, body_start, body.text.as_str());
+                        sources.push(body.with_source(&body_span));
+                    } else {
+                        // the body's text isn't in the document, so point at
+                        // the statement that defines it
+                        sources.push(body.with_source(&span));
+                    }
+                    statement_children.push(StatementChild {
+                        parent_id: stmt.id,
+                        child_id: body.id,
+                        start_offset: body_offset,
+                        end_offset: body_offset.map(|start| start + body.text.len()),
+                    });
+                    bodies.push(body);
+                }
+            }
+            cursor = next;
+            statements.push(stmt);
+        }
+        statements.append(&mut bodies);
+        for statement in statements.iter().filter(|&s| s.language == Language::PgSql) {
+            let result = statement.fingerprint();
+            predictions.push(Prediction::from_fingerprint(statement, &result));
+            if let Ok(fingerprint) = result {
+                statement_fingerprints.push((statement.id, fingerprint));
+                if let Ok(text) = statement.normalize() {
+                    normalized.push((statement.id, text));
+                }
+            }
+            if self.store_ast {
+                if let Ok(ast) = statement.ast() {
+                    statement_asts.push(ast);
+                }
+            }
+        }
+
+        let mut statement_languages: Vec<(i64, Language)> =
+            Vec::with_capacity(statements.len() + pl_blocks.len());
+
+        for s in statements.as_slice() {
+            statement_languages.push((s.id as i64, s.language));
+        }
+        for row in pl_blocks.as_slice() {
+            statement_languages.push(row.to_owned())
+        }
+
+        // a source has one locator, so only the first template links lines
+        if let Some(template) = self.templates.first() {
+            for src in sources.iter_mut() {
+                src.locator = Some(
+                    template
+                        .statement_url(
+                            self.reference.as_str(),
+                            doc.path(),
+                            src.span.start_line,
+                            src.span.end_line,
+                        )
+                        .map_err(Failure::Other)?,
+                );
+            }
+        }
+        return Ok(SplitDocument {
+            urls,
+            statements,
+            fingerprints: statement_fingerprints,
+            asts: statement_asts,
+            predictions,
+            normalized,
+            languages: statement_languages,
+            sources,
+            children: statement_children,
+            split_failure,
+        });
+    }
+}
+
+// corpus databases ------------------------------------------------------------
+
+/// which hashes of each statement's text to store
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdHash {
+    /// only the xxhash3_64 id
+    Xxh3_64,
+    /// the xxhash3_64 id plus the xxhash3_128 in `statements.xxh3_128`, for
+    /// verifying that ids are unique
+    Xxh3_128,
+}
+impl Default for IdHash {
+    fn default() -> Self {
+        IdHash::Xxh3_64
+    }
+}
+
+/// metadata recorded alongside every document in an ingest
+#[derive(Clone, Debug, Default)]
+pub struct IngestOptions {
+    /// the spdx identifier of the license governing the documents' urls
+    pub spdx: Option<String>,
+    /// the full text of that license
+    pub license: Option<String>,
+    /// the postgres version to which the documents belong
+    pub pg_version: Option<String>,
+    pub id_hash: IdHash,
+    /// the arguments the splitter was run with, recorded in `runs`
+    pub command_line: Vec<String>,
+    /// the files, directories, or tarballs the documents were read from
+    pub input_roots: Vec<String>,
+    /// re-split documents that were last written by a different splitter,
+    /// psql_splitter, or libpg_query version rather than skipping them
+    pub reprocess_stale: bool,
+}
+
+/// what happened to a single document during an ingest
+pub enum Ingested<'a> {
+    /// the document was split and written
+    Split(&'a SplitDocument),
+    /// the document was already in the corpus, so only its urls and version
+    /// were recorded
+    AlreadyProcessed,
+    /// the document couldn't be decoded or split, so it was skipped
+    Skipped(&'a Failure),
+}
+
+/// a corpus database
+pub struct Corpus {
+    conn: Connection,
+}
+
+impl Corpus {
+    /// open or initialize a corpus database
+    pub fn open(path: &str) -> Result<Self, Failure> {
+        return Ok(Corpus {
+            conn: sqlite::connect(path)?,
+        });
+    }
+
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    pub fn close(self) -> Result<(), Failure> {
+        self.conn.close().map_err(|(_, e)| e)?;
+        return Ok(());
+    }
+
+    /// split and write every document in a single transaction, splitting on
+    /// `jobs` threads, and calling `report` with the outcome for each
+    /// document's path in the order given
+    pub fn ingest<D, F>(
+        &mut self,
+        splitter: &Splitter,
+        documents: D,
+        options: &IngestOptions,
+        jobs: usize,
+        report: F,
+    ) -> Result<(), Failure>
+    where
+        D: IntoIterator<Item = Result<InputDocument, Failure>>,
+        F: FnMut(&str, Ingested),
+    {
+        let mut writer = sqlite::SqliteWriter::new(&mut self.conn, options)?;
+        return writer::ingest_parallel(&mut writer, splitter, documents, jobs, report);
+    }
+
+    /// re-split and rewrite every incomplete document found among `documents`
+    /// in a single transaction. Returns the ids of the incomplete documents
+    /// that weren't repaired.
+    pub fn repair<D, F>(
+        &mut self,
+        splitter: &Splitter,
+        documents: D,
+        options: &IngestOptions,
+        mut report: F,
+    ) -> Result<Vec<i64>, Failure>
+    where
+        D: IntoIterator<Item = Result<InputDocument, Failure>>,
+        F: FnMut(&str, Ingested),
+    {
+        let mut incomplete: Vec<i64> = sqlite::incomplete_documents(&self.conn)?;
+        let mut writer = sqlite::SqliteWriter::new(&mut self.conn, options)?;
+        writer.rewrite_documents(incomplete.as_slice());
+        for input in documents {
+            let input = input?;
+            let path = input.path.clone();
+            let doc = match splitter.decode(input) {
+                Ok(doc) => doc,
+                Err(e) => {
+                    report(path.as_str(), Ingested::Skipped(&e));
+                    continue;
+                }
+            };
+            if let Some(i) = incomplete.iter().position(|id| *id == doc.id()) {
+                if writer::write_document(&mut writer, splitter, &doc, &mut report)? {
+                    incomplete.remove(i);
+                }
+            }
+        }
+        writer.commit()?;
+        return Ok(incomplete);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::InputDocument;
+
+    /// the offset of `body` in `text`, with pg_query's location of the string
+    /// constant or `AS` keyword at `keyword`
+    fn offset(text: &str, keyword: &str, body: &str) -> Option<usize> {
+        let location = text.find(keyword).unwrap();
+        return pl_body_offset(text, location, body);
+    }
+
+    #[test]
+    fn finds_dollar_quoted_bodies() {
+        let text = "DO $$ BEGIN NULL; END $$";
+        assert_eq!(offset(text, "$$", " BEGIN NULL; END "), Some(5));
+        let text = "create function f() returns int as $body$ select 1 $body$ language sql";
+        assert_eq!(offset(text, "as", " select 1 "), Some(41));
+    }
+
+    #[test]
+    fn skips_comments_before_the_body() {
+        let text = "create function f() returns int AS -- the body\n /* a /* nested */ comment */ 'select 1' language sql";
+        let at = offset(text, "AS", "select 1").unwrap();
+        assert_eq!(&text[at..at + 8], "select 1");
+        assert_eq!(
+            skip_whitespace_and_comments("  -- a\n/* b /* c */ d */ x"),
+            "x"
+        );
+        assert_eq!(skip_whitespace_and_comments("-- only a comment"), "");
+    }
+
+    #[test]
+    fn quoted_bodies_with_escapes_have_no_offset() {
+        let text = "DO 'BEGIN RAISE NOTICE ''hi''; END'";
+        assert_eq!(offset(text, "'", "BEGIN RAISE NOTICE 'hi'; END"), None);
+        let text = "DO 'BEGIN NULL; END'";
+        assert_eq!(offset(text, "'", "BEGIN NULL; END"), Some(4));
+        let text = "DO E'BEGIN NULL; END'";
+        assert_eq!(offset(text, "E'", "BEGIN NULL; END"), None);
+    }
+
+    #[test]
+    fn repairs_keep_old_rows_until_rewritten() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        sqlite::init(&mut conn).unwrap();
+        let mut corpus = Corpus { conn };
+        let splitter = Splitter::default();
+        let documents = || {
+            return vec![Ok(InputDocument {
+                path: "a.sql".to_string(),
+                bytes: b"select 1;\nselect 2;\n".to_vec(),
+            })];
+        };
+        let options = IngestOptions::default();
+        corpus
+            .ingest(&splitter, documents(), &options, 1, |_, _| {})
+            .unwrap();
+        let rows = |corpus: &Corpus| -> (i64, bool) {
+            return corpus
+                .connection()
+                .query_row(
+                    "SELECT (SELECT count(*) FROM document_statements), complete FROM documents",
+                    [],
+                    |row| Ok((row.get(0)?, row.get(1)?)),
+                )
+                .unwrap();
+        };
+        let (sources, complete) = rows(&corpus);
+        assert!(sources > 0 && complete);
+        assert!(sqlite::incomplete_documents(corpus.connection())
+            .unwrap()
+            .is_empty());
+
+        // a rewrite that fails partway through leaves the old rows alone
+        corpus
+            .connection()
+            .execute_batch(
+                "UPDATE documents SET complete = 0;
+                UPDATE statements SET \"text\" = \"text\" || ' -- tampered' WHERE \"text\" LIKE '%select 2%';",
+            )
+            .unwrap();
+        let mut skipped = 0;
+        let unrepaired = corpus
+            .repair(&splitter, documents(), &options, |_, outcome| {
+                if let Ingested::Skipped(_) = outcome {
+                    skipped += 1;
+                }
+            })
+            .unwrap();
+        assert_eq!(skipped, 1);
+        assert_eq!(unrepaired.len(), 1);
+        assert_eq!(rows(&corpus), (sources, false));
+
+        corpus
+            .connection()
+            .execute_batch(
+                "UPDATE statements SET \"text\" = replace(\"text\", ' -- tampered', '');",
+            )
+            .unwrap();
+        let unrepaired = corpus
+            .repair(&splitter, documents(), &options, |_, _| {})
+            .unwrap();
+        assert!(unrepaired.is_empty());
+        assert_eq!(rows(&corpus), (sources, true));
+    }
+}
@@ -1,265 +1,19 @@
-use lazy_static::lazy_static;
-use pg_query_wrapper as pg_query;
-use psql_splitter;
-use regex::Regex;
-use sqlite::doc_already_processed;
-mod sqlite;
+use splitter::{
+    encoding, export, fixtures,
+    input::{self, InputDocuments},
+    jsonl, minimize,
+    progress::Progress,
+    sqlite,
+    url_template::UrlTemplate,
+    writer, Corpus, Failure, IdHash, IngestOptions, Ingested, SplitDocument, Splitter,
+};
 use std::collections::HashSet;
-use std::convert::TryInto;
-use std::io::Read;
-use std::process;
 use std::{
     fs::{self, File},
-    io,
+    io::{self, Write},
     os::unix::prelude::PermissionsExt,
     path::{Path, PathBuf},
 };
-use xxhash_rust::xxh3::xxh3_64;
-#[derive(Debug)]
-pub enum Failure {
-    IoErr(io::Error),
-    PgQueryError(pg_query::Failure),
-    DirDne,
-    NotDir,
-    Sqlite(rusqlite::Error),
-    Other(String),
-}
-impl From<io::Error> for Failure {
-    fn from(e: io::Error) -> Self {
-        Self::IoErr(e)
-    }
-}
-impl From<pg_query::Failure> for Failure {
-    fn from(e: pg_query::Failure) -> Self {
-        Self::PgQueryError(e)
-    }
-}
-impl From<rusqlite::Error> for Failure {
-    fn from(e: rusqlite::Error) -> Self {
-        Failure::Sqlite(e)
-    }
-}
-#[derive(Clone, Debug)]
-pub struct Statement {
-    text: String,
-    /// the xxhash3_64 of the overall utf-8 document that this statement is
-    /// drawn from
-    document_id: i64,
-    /// the xxhash3_64 of the text
-    id: i64,
-    /// might include line numbers inside a collection
-    language: Language,
-    // urls: Vec<String>,
-    // start_line: usize,
-    n_lines: usize,
-}
-
-impl Statement {
-    fn new(text: String, language: Language, document_id: i64) -> Self {
-        let n_lines = text.matches("\n").count();
-        Statement {
-            id: xxh3_64(text.as_bytes()) as i64,
-            document_id,
-            text,
-            language,
-            n_lines,
-        }
-    }
-    fn fingerprint(self: &Self) -> Result<i64, Failure> {
-        let (fingerprint, _) = pg_query::fingerprint(self.text.clone().as_str())?;
-        return Ok(fingerprint as i64);
-    }
-    fn with_source(
-        self: &Self,
-        url: &str,
-        start_line: usize,
-        start_offset: usize,
-    ) -> StatementSource {
-        StatementSource {
-            statement_id: self.id,
-            url: url.to_owned(),
-            document_id: self.document_id,
-            start_line,
-            start_offset,
-            end_offset: start_offset + self.text.len(),
-            n_lines: self.n_lines,
-        }
-    }
-}
-
-#[derive(Clone)]
-pub struct StatementSource {
-    statement_id: i64,   //
-    start_line: usize,   // 1-indexed
-    n_lines: usize,      // can be 0
-    start_offset: usize, // 0-indexed length in bytes, **not** unicode code points
-    end_offset: usize,   // = start_offset + statement.len()
-    document_id: i64,    // xxhash3_64 of the overall document from which this statement is drawn
-    url: String,         // TODO: validate; can currently be "" or "file://"
-}
-impl StatementSource {
-    fn url_id(&self) -> i64 {
-        xxh3_64(self.url.as_bytes()) as i64
-    }
-}
-
-fn extract_protobuf_string(node: &Box<pg_query::pbuf::Node>) -> String {
-    use pg_query::pbuf::node::Node;
-    match node.node.as_ref().unwrap() {
-        Node::String(s) => return s.str.clone(),
-        _ => panic!("node not string"),
-    }
-}
-fn parse_pl(nodes: &Vec<pg_query::pbuf::Node>) -> String {
-    use pg_query::pbuf::node::Node;
-
-    // let mut content: String = "".into();
-    let mut lang: String = "plpgsql".into();
-    for node in nodes {
-        // unwrapping aggressively to catch unexpected structures via panics
-        if let Node::DefElem(inner) = node.node.as_ref().unwrap() {
-            match inner.defname.as_str() {
-                // "as" => match inner.arg.as_ref().unwrap().node.as_ref().unwrap() {
-                //     Node::String(s) => content = s.str.clone(),
-                //     Node::List(l) => {
-                //         assert!(l.items.len() >= 1); // for example, `LANGUAGE C STRICT` comes across as 2 items
-                //         let item = &l.items[0];
-                //         match item.node.as_ref().unwrap() {
-                //             Node::String(s) => content = s.str.clone(),
-                //             _ => panic!("unexpected list-item type {:?}", item),
-                //         }
-                //     }
-                //     _ => panic!("unexpected pl option {:?}", inner.as_ref()),
-                // },
-                "language" => lang = extract_protobuf_string(inner.arg.as_ref().unwrap()),
-                _ => {} // ignore
-            }
-        }
-    }
-    return lang;
-}
-fn parse_do_stmt(d: &pg_query::pbuf::DoStmt) -> String {
-    return parse_pl(d.args.as_ref());
-}
-fn parse_fn_stmt(f: &pg_query::pbuf::CreateFunctionStmt) -> String {
-    return parse_pl(f.options.as_ref());
-}
-
-// TODO: recognize pl blocks, don't extract them. They often need their context to parse
-// successfully. For example `DO $$ BEGIN RETURN QUERY ... $$` isn't valid
-fn extract_pl(input: &str) -> Result<String, Failure> {
-    use pg_query::pbuf::node::Node;
-    let stmts = pg_query::parse_to_protobuf(input)?.stmts;
-
-    // for some reason there's a section in partition_prune that doesn't get
-    // split when the entire document is passed via --input.  Passing the text
-    // via stdin, however, causes the correct splits.
-    // I'm ignoring it for now, since it only causes one snag in the entire regression
-    // test suite.
-    // if stmts.len() != 1 {
-    //     println!("--------------------------------------------------");
-    //     println!("{}", input);
-    //     println!("==================================================");
-    //     println!("{:?}", stmts);
-    // }
-
-    // sometimes there'll be empty 0-length statements, e.g. `/* empty query */;`
-    if stmts.len() == 0 {
-        return Err(Failure::Other("empty stmt (comment-only?)".to_string()));
-    }
-    if let Some(node) = &stmts[0].stmt {
-        if let Some(node) = &node.node {
-            match node {
-                Node::DoStmt(stmt) => return Ok(parse_do_stmt(stmt)),
-                Node::CreateFunctionStmt(stmt) => return Ok(parse_fn_stmt(stmt)),
-                _ => return Err(Failure::Other(format!("unexpected node type {:?}", node))),
-            }
-        } else {
-            return Err(Failure::Other("missing statement-node".to_string()));
-        }
-    } else {
-        return Err(Failure::Other("empty stmt".to_string()));
-    }
-}
-
-// psql stuff ---------------------------------------------------
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(i64)]
-pub enum Language {
-    PgSql = 0,
-    PlPgSql = 1,
-    Psql = 2,
-    PlPerl = 3,
-    PlTcl = 4,
-    PlPython2 = 5,
-    PlPython3 = 6,
-    Other = -1,
-}
-
-lazy_static! {
-    static ref PLPGSQL_NAME: Regex = Regex::new("(?i)^plpgsql$").unwrap();
-    static ref PLPERL_NAME: Regex = Regex::new("(?i)^plperl$").unwrap();
-    static ref PLTCL_NAME: Regex = Regex::new("(?i)^pltcl$").unwrap();
-    static ref PLPYTHON2_NAME: Regex = Regex::new("(?i)^plpython2?u$").unwrap();
-    static ref PLPYTHON3_NAME: Regex = Regex::new("(?i)^plpython3u$").unwrap();
-}
-
-fn identify_language(lang: &str) -> Language {
-    use Language::*;
-    if let Some(_) = PLPGSQL_NAME.find(lang) {
-        return PlPgSql;
-    } else if let Some(_) = PLPERL_NAME.find(lang) {
-        return PlPerl;
-    } else if let Some(_) = PLTCL_NAME.find(lang) {
-        return PlTcl;
-    } else if let Some(_) = PLPYTHON2_NAME.find(lang) {
-        return PlPython2;
-    } else if let Some(_) = PLPYTHON3_NAME.find(lang) {
-        return PlPython3;
-    } else {
-        return Other;
-    }
-}
-
-fn text_to_statement(text: &str, document_id: i64) -> Statement {
-    if psql_splitter::is_psql(text) {
-        return Statement::new(text.to_string(), Language::Psql, document_id);
-    } else {
-        return Statement::new(text.to_string(), Language::PgSql, document_id);
-    }
-}
-
-fn recognize_pl_statement(s: &Statement) -> Option<(i64, Language)> {
-    if let Ok(lang) = extract_pl(s.text.as_str()) {
-        return Some((s.id as i64, identify_language(lang.as_str())));
-    } else {
-        return None;
-    }
-}
-
-fn split_psql_to_statements(input: String) -> Vec<String> {
-    let mut statements: Vec<String> = vec![];
-    let mut rest = input.as_str();
-    while let Ok((r, text)) = psql_splitter::statement(rest) {
-        statements.push(text.to_string());
-        rest = r;
-    }
-    assert_eq!(
-        rest,
-        "",
-        "did not consume >>>{}<<<",
-        &input[..input.len() - rest.len()]
-    );
-    assert_eq!(input, statements.join("").as_str());
-    let act_len = statements
-        .iter()
-        .map(|s| s.len())
-        .reduce(|total, len| total + len)
-        .unwrap();
-    assert_eq!(input.len(), act_len);
-    return statements;
-}
 
 // CLI stuff -------------------------------------------------------------------
 
@@ -282,6 +36,18 @@ fn validate_output_target(output: String) -> Result<(), String> {
     return Ok(());
 }
 
+fn validate_url_template(template: String) -> Result<(), String> {
+    return UrlTemplate::parse(template.as_str()).map(|_| ());
+}
+
+fn validate_encoding(name: String) -> Result<(), String> {
+    if name == "auto" || encoding::pg_encoding(name.as_str()).is_some() {
+        return Ok(());
+    } else {
+        return Err(format!("unsupported encoding {}", name));
+    }
+}
+
 fn validate_license_file(license: String) -> Result<(), String> {
     let path = PathBuf::from(license.clone());
     if !path.exists() {
@@ -301,26 +67,6 @@ fn validate_license_file(license: String) -> Result<(), String> {
     }
 }
 
-fn is_flat_dir_of_readable_files(path: PathBuf) -> bool {
-    if let Ok(mut dir_entries) = std::fs::read_dir(path) {
-        return dir_entries.all(|f| {
-            if let Ok(file) = f {
-                let path = file.path();
-                if !path.is_file() {
-                    return false;
-                }
-                match file_is_readable(path) {
-                    Ok(is_readable) => return is_readable,
-                    _ => return false,
-                }
-            } else {
-                return false;
-            }
-        });
-    } else {
-        return false;
-    }
-}
 const READ_BITS: u32 = 0o444;
 // const WRITE_BITS: u32 = 0o222;
 
@@ -344,6 +90,21 @@ where
 //     return Ok(fs::metadata(file)?.permissions().mode() & WRITE_BITS > 0);
 // }
 
+fn validate_count(count: String) -> Result<(), String> {
+    return match count.parse::<usize>() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{}: {}", count, e)),
+    };
+}
+
+fn validate_jobs(jobs: String) -> Result<(), String> {
+    return match jobs.parse::<usize>() {
+        Ok(0) => Err("--jobs must be at least 1".to_string()),
+        Ok(_) => Ok(()),
+        Err(e) => Err(format!("{}: {}", jobs, e)),
+    };
+}
+
 fn validate_input_source(input: String) -> Result<(), String> {
     if input == "stdin" {
         // println!("reading from stdin");
@@ -354,11 +115,9 @@ fn validate_input_source(input: String) -> Result<(), String> {
         return Err(format!("input path {} does not exist", input).into());
     }
     if input_path.is_dir() {
-        // TODO: reject; only 1 file at a time
-        if is_flat_dir_of_readable_files(input_path) {
-            return Ok(());
-        } else {
-            return Err(format!("{} isn't a flat directory of readable files", input).into());
+        match fs::read_dir(input_path) {
+            Ok(_) => return Ok(()),
+            Err(e) => return Err(format!("unable to read directory {}: {}", input, e)),
         }
     } else if input_path.is_file() {
         match file_is_readable(input_path) {
@@ -376,96 +135,266 @@ fn validate_input_source(input: String) -> Result<(), String> {
     }
 }
 
-fn process_doc(
-    doc: &str,
-    document_id: i64,
-    urls: &[&str],
-    do_count: bool,
-    do_debug: bool,
-) -> (
-    Vec<Statement>,
-    Vec<(i64, i64)>,
-    Vec<(i64, Language)>,
-    Vec<StatementSource>,
-) {
-    let splits = split_psql_to_statements(doc.to_owned());
-    let mut statements = Vec::<Statement>::with_capacity(splits.capacity());
-    let mut sources = Vec::<StatementSource>::with_capacity(urls.len() * splits.len());
-    let mut statement_fingerprints = Vec::<(i64, i64)>::with_capacity(splits.len());
-    let mut line_number = 1usize;
-    let mut offset = 0usize;
-    for split in splits {
-        let stmt = text_to_statement(split.as_str(), document_id);
-        for url in urls.clone() {
-            let src = stmt.with_source(url, line_number, offset);
-            sources.push(src)
+fn validate_corpus_db(db: String) -> Result<(), String> {
+    if PathBuf::from(db.clone()).is_file() {
+        return Ok(());
+    } else {
+        return Err(format!("{} is not a file", db));
+    }
+}
+
+fn migrate(matches: &clap::ArgMatches) -> Result<(), Failure> {
+    let path = matches.value_of("db").unwrap();
+    let mut conn = rusqlite::Connection::open(path)?;
+    let version = sqlite::schema_version(&conn)?;
+    if matches.is_present("dry_run") {
+        for migration in sqlite::pending_migrations(version)? {
+            println!(
+                "-- {}.{} -> {}.{}",
+                migration.from.0, migration.from.1, migration.to.0, migration.to.1
+            );
+            println!("{}", migration.sql);
+            println!(
+                "INSERT INTO schema_version(major, minor) VALUES ({}, {});",
+                migration.to.0, migration.to.1
+            );
         }
-        line_number += stmt.n_lines;
-        offset += stmt.text.len();
-        statements.push(stmt);
+        return Ok(());
+    }
+    let applied = sqlite::migrate(&mut conn)?;
+    match applied.last() {
+        Some(last) => println!(
+            "migrated {} from {}.{} to {}.{}",
+            path, version.0, version.1, last.to.0, last.to.1
+        ),
+        None => println!("{} is already at {}.{}", path, version.0, version.1),
     }
-    for statement in statements.iter().filter(|&s| s.language == Language::PgSql) {
-        if let Ok(fingerprint) = statement.fingerprint() {
-            statement_fingerprints.push((statement.id, fingerprint));
+    conn.close().map_err(|(_, e)| e)?;
+    return Ok(());
+}
+
+fn merge(matches: &clap::ArgMatches) -> Result<(), Failure> {
+    let inputs: Vec<&str> = matches.values_of("inputs").unwrap().collect();
+    let mut conn = sqlite::connect(matches.value_of("out").unwrap())?;
+    let merged = sqlite::merge(&mut conn, inputs.as_slice())?;
+    for table in merged {
+        println!(
+            "{:24} {:8} inserted {:6} conflicts",
+            table.name, table.inserted, table.conflicts
+        );
+        if table.conflicts > 0 {
+            eprintln!(
+                "{}: {} rows conflicted with existing rows and were skipped",
+                table.name, table.conflicts
+            );
         }
     }
+    conn.close().map_err(|(_, e)| e)?;
+    return Ok(());
+}
 
-    let pl_blocks: Vec<(i64, Language)> = statements
-        .iter()
-        .filter_map(recognize_pl_statement)
-        .collect();
+fn export(matches: &clap::ArgMatches) -> Result<(), Failure> {
+    let conn = sqlite::connect(matches.value_of("db").unwrap())?;
+    let out: Box<dyn Write> = match matches.value_of("out").unwrap() {
+        "stdout" => Box::new(io::stdout()),
+        path => Box::new(File::create(path)?),
+    };
+    let mut out = io::BufWriter::new(out);
+    // pg-copy is the only --format so far
+    export::write_pg_copy(&conn, &mut out)?;
+    conn.close().map_err(|(_, e)| e)?;
+    return Ok(());
+}
+
+fn export_fixtures(matches: &clap::ArgMatches) -> Result<(), Failure> {
+    let conn = sqlite::connect(matches.value_of("db").unwrap())?;
+    let out_dir = PathBuf::from(matches.value_of("out").unwrap());
+    for version in fixtures::export_fixtures(&conn, out_dir.as_path())? {
+        println!(
+            "{:>6}: {:6} fixtures from {:4} files",
+            version.version, version.fixtures, version.files
+        );
+    }
+    conn.close().map_err(|(_, e)| e)?;
+    return Ok(());
+}
 
-    let mut statement_languages: Vec<(i64, Language)> =
-        Vec::with_capacity(statements.len() + pl_blocks.len());
+fn dedupe_report(matches: &clap::ArgMatches) -> Result<(), Failure> {
+    let conn = sqlite::connect(matches.value_of("db").unwrap())?;
+    let min_statements: usize = matches.value_of("min_count").unwrap().parse().unwrap();
+    let limit: Option<usize> = matches.value_of("limit").map(|n| n.parse().unwrap());
+    let groups = sqlite::fingerprint_groups(&conn, min_statements, limit)?;
+    let stdout = io::stdout();
+    let mut out = io::BufWriter::new(stdout.lock());
+    writeln!(out, "fingerprint\tstatements\tnormalized\texample")?;
+    for group in groups.iter() {
+        writeln!(
+            out,
+            "{:016x}\t{}\t{}\t{:?}",
+            group.fingerprint, group.statements, group.normalized, group.example
+        )?;
+    }
+    out.flush()?;
+    eprintln!(
+        "{} fingerprints covering {} statements",
+        groups.len(),
+        groups.iter().map(|g| g.statements).sum::<usize>()
+    );
+    conn.close().map_err(|(_, e)| e)?;
+    return Ok(());
+}
 
-    for s in statements.as_slice() {
-        statement_languages.push((s.id as i64, s.language));
+fn minimize(matches: &clap::ArgMatches) -> Result<(), Failure> {
+    let src = sqlite::connect(matches.value_of("db").unwrap())?;
+    let mut dst = sqlite::connect(matches.value_of("out").unwrap())?;
+    let minimized = minimize::minimize(&src, &mut dst)?;
+    println!(
+        "{} statements from {} documents cover {} fingerprints, node types, and languages",
+        minimized.statements, minimized.documents, minimized.features
+    );
+    dst.close().map_err(|(_, e)| e)?;
+    src.close().map_err(|(_, e)| e)?;
+    return Ok(());
+}
+
+/// read from stdin, a file, a directory, or a tarball
+fn read_documents(matches: &clap::ArgMatches) -> Result<InputDocuments, Failure> {
+    let pattern = glob::Pattern::new(matches.value_of("glob").unwrap())
+        .map_err(|e| Failure::Other(format!("invalid --glob: {}", e)))?;
+    return input::read_input(matches.value_of("input").unwrap(), &pattern);
+}
+
+fn build_splitter(matches: &clap::ArgMatches) -> Result<Splitter, Failure> {
+    // TODO: validate each URL
+    let urls: Vec<String> = match matches.values_of("url") {
+        Some(url_args) => url_args.map(|url| url.to_string()).collect(),
+        None => vec![],
+    };
+    let mut templates: Vec<UrlTemplate> = vec![];
+    if let Some(template_args) = matches.values_of("url_template") {
+        for template in template_args {
+            // already validated
+            templates.push(UrlTemplate::parse(template).unwrap());
+        }
     }
-    for row in pl_blocks.as_slice() {
-        statement_languages.push(row.to_owned())
+    if !matches.is_present("ref") && templates.iter().any(|t| t.uses_ref()) {
+        return Err(Failure::Other(
+            "--url-template uses {ref}, but no --ref was given".to_string(),
+        ));
+    }
+    return Ok(Splitter {
+        urls,
+        templates,
+        reference: matches.value_of("ref").unwrap_or("").to_string(),
+        encoding: match matches.value_of("encoding").unwrap() {
+            "auto" => None,
+            name => encoding::pg_encoding(name), // already validated
+        },
+        best_effort: matches.is_present("best_effort"),
+        unicode_positions: matches.is_present("unicode_positions"),
+        store_ast: matches.is_present("store_ast"),
+    });
+}
+
+fn ingest_options(matches: &clap::ArgMatches) -> Result<IngestOptions, Failure> {
+    if matches.is_present("license") && !matches.is_present("spdx") {
+        return Err(Failure::Other(format!(
+            "missing the spdx identifier for {}",
+            matches.value_of("license").unwrap()
+        )));
     }
+    return Ok(IngestOptions {
+        spdx: matches.value_of("spdx").map(|spdx| spdx.to_string()),
+        license: match matches.value_of("license") {
+            Some(license_path) => Some(fs::read_to_string(license_path)?),
+            None => None,
+        },
+        pg_version: matches.value_of("pg_version").map(|v| v.to_string()),
+        id_hash: match matches.value_of("id_hash").unwrap() {
+            "xxh3-128" => IdHash::Xxh3_128,
+            _ => IdHash::Xxh3_64,
+        },
+        command_line: std::env::args().collect(),
+        input_roots: {
+            let input = matches.value_of("input").unwrap();
+            // absolute, so that runs from different directories can be compared
+            match fs::canonicalize(input) {
+                Ok(path) if input != "stdin" => vec![path.display().to_string()],
+                _ => vec![input.to_string()],
+            }
+        },
+        reprocess_stale: matches.is_present("reprocess_stale"),
+    });
+}
 
-    if do_count {
-        let mut ids = HashSet::new();
-        for s in statements.clone() {
-            ids.insert(s.id);
-        }
-        for (statement_id, _) in pl_blocks.as_slice() {
-            ids.insert(*statement_id);
-        }
-        println!(
-            "{:6} unique, {:6} total statements",
-            ids.len(),
-            statements.len()
-        );
+/// `matches` holds the top-level options the documents were first ingested with
+fn repair(matches: &clap::ArgMatches, repair_matches: &clap::ArgMatches) -> Result<(), Failure> {
+    let options = ingest_options(matches)?;
+    let splitter = build_splitter(matches)?;
+    let documents = read_documents(matches)?;
+    let mut corpus = Corpus::open(repair_matches.value_of("db").unwrap())?;
+    let mut repaired: usize = 0;
+    let unrepaired = corpus.repair(&splitter, documents, &options, |path, outcome| {
+        match outcome {
+            Ingested::Split(_) => {
+                repaired += 1;
+                println!("repaired {}", path);
+            }
+            Ingested::AlreadyProcessed => {}
+            Ingested::Skipped(e) => eprintln!("{}: {:?}", path, e),
+        };
+    })?;
+    corpus.close()?;
+    println!("repaired {} documents", repaired);
+    for id in unrepaired {
+        eprintln!("document {:x} is still incomplete", id);
     }
+    return Ok(());
+}
 
-    if do_debug {
-        for s in statements.as_slice() {
-            let id = s.id;
-            println!(
-                "-- {:?} {:x} --------------------------------------",
-                s.language, s.id
-            );
-            for src in sources.iter().filter(|src| src.statement_id == id) {
-                println!(
-                    "-- {}#L{}-L{}",
-                    src.url,
-                    src.start_line,
-                    src.start_line + src.n_lines - 1
-                );
+fn print_count(log: &mut dyn Write, path: &str, split: &SplitDocument) -> io::Result<()> {
+    let statements = split.statements();
+    let ids: HashSet<i64> = statements.iter().map(|s| s.id()).collect();
+    return writeln!(
+        log,
+        "{:6} unique, {:6} total statements {}",
+        ids.len(),
+        statements.len(),
+        path
+    );
+}
+
+fn print_debug(log: &mut dyn Write, split: &SplitDocument) -> io::Result<()> {
+    for s in split.statements() {
+        let id = s.id();
+        writeln!(
+            log,
+            "-- {:?} {:x} --------------------------------------",
+            s.language(),
+            s.id()
+        )?;
+        for src in split
+            .sources()
+            .iter()
+            .filter(|src| src.statement_id() == id)
+        {
+            let (start_line, end_line) = src.span().lines();
+            if split.urls().is_empty() {
+                writeln!(log, "-- L{}-L{}", start_line, end_line)?;
+            }
+            for url in split.urls() {
+                writeln!(log, "-- {}#L{}-L{}", url, start_line, end_line)?;
             }
-            println!("---------------------------------------------------------------");
-            println!("{}", s.text);
         }
+        writeln!(
+            log,
+            "---------------------------------------------------------------"
+        )?;
+        writeln!(log, "{}", s.text())?;
     }
-    return (
-        statements,
-        statement_fingerprints,
-        statement_languages,
-        sources,
-    );
+    return Ok(());
 }
+
 fn main() -> Result<(), Failure> {
     let matches = clap::App::new("splitter")
         .arg(
@@ -478,15 +407,78 @@ fn main() -> Result<(), Failure> {
                 .long_help("the file or device to which to write output (default stdout)")
                 .validator(validate_output_target),
         )
+        .arg(
+            clap::Arg::with_name("format")
+                .long("--format")
+                .takes_value(true)
+                .possible_values(&["jsonl", "sqlite"])
+                .help("the output format [default: jsonl for stdout, else sqlite]")
+                .long_help("the output format. `jsonl` writes one json object per line for each distinct statement in each document, with its id, document id, path, text, languages, fingerprint, document urls, and sources. `sqlite` writes a corpus db. Defaults to jsonl when writing to stdout and sqlite otherwise."),
+        )
         .arg(
             clap::Arg::with_name("input")
                 .long("--input")
                 .short("-i")
                 .default_value("stdin")
-                .help("file or device from which to read input")
-                .long_help("the file or device from which to read SQL")
+                .help("file, directory, or tarball from which to read input")
+                .long_help("the file, device, directory, or .tar/.tar.gz from which to read SQL. Directories are read recursively in order of path, skipping symlinked directories. Tarballs are read in archive order, and if every file is in one top-level directory, that directory is left out of their paths.")
                 .validator(validate_input_source),
         )
+        .arg(
+            clap::Arg::with_name("glob")
+                .long("--glob")
+                .takes_value(true)
+                .default_value("*.sql")
+                .help("which files in a directory or tarball to read")
+                .long_help("a glob matched against the path of each file in a directory or tarball, relative to its root"),
+        )
+        .arg(
+            clap::Arg::with_name("encoding")
+                .long("--encoding")
+                .takes_value(true)
+                .default_value("auto")
+                .help("the encoding of the input, e.g. UTF8 or LATIN1")
+                .long_help("the postgres name of the input's encoding, e.g. UTF8, LATIN1, or EUC_JP. `auto` tries utf-8, then any encoding declared via `SET client_encoding` or `\\encoding`, then LATIN1.")
+                .validator(validate_encoding),
+        )
+        .arg(
+            clap::Arg::with_name("best_effort")
+                .long("--best-effort")
+                .takes_value(false)
+                .help("keep partially-split documents")
+                .long_help("keep the statements split from a document that can't be split completely, storing the remainder as a single statement in the language `other` and recording the document in split_failures"),
+        )
+        .arg(
+            clap::Arg::with_name("unicode_positions")
+                .long("--unicode-positions")
+                .takes_value(false)
+                .help("also record columns and char and utf-16 offsets")
+                .long_help("also record each statement's start and end columns and its char and utf-16 offsets in document_statements, e.g. for editors using the language server protocol. Off by default since it means visiting every char of every document."),
+        )
+        .arg(
+            clap::Arg::with_name("store_ast")
+                .long("--store-ast")
+                .takes_value(false)
+                .help("also store pg_query's parse tree of each statement")
+                .long_help("also store pg_query's parse tree of each pgsql statement it can parse in statement_asts, as a protobuf-encoded pg_query.ParseResult keyed by statement id and libpg_query version. Only written to sqlite output."),
+        )
+        .arg(
+            clap::Arg::with_name("reprocess_stale")
+                .long("--reprocess-stale")
+                .takes_value(false)
+                .help("re-split documents ingested by other splitter versions")
+                .long_help("re-split documents that were last ingested by a different splitter version or commit, psql_splitter commit, or libpg_query version (or before runs were recorded) instead of skipping them as already processed. Only applies to sqlite output."),
+        )
+        .arg(
+            clap::Arg::with_name("jobs")
+                .long("--jobs")
+                .short("-j")
+                .takes_value(true)
+                .default_value("1")
+                .help("the number of threads with which to split documents")
+                .long_help("the number of threads with which to split, classify, and fingerprint documents. Documents are still written one at a time in input order, so the rows written are the same for any number of jobs, and so is --output stdout's json. A sqlite file isn't byte-for-byte comparable with another, though, even one from an identical run: each run's `runs` row and `documents.run_id` hold the time and command line of that run.")
+                .validator(validate_jobs),
+        )
         .arg(
             clap::Arg::with_name("debug")
                 .long("--debug")
@@ -499,13 +491,38 @@ fn main() -> Result<(), Failure> {
             .takes_value(true)
             .multiple(true)
             .help("urls at which the input may be found.")
-            .long_help("urls at which the input may be found, e.g. multiple git hosts each with a branch and commit"),
+            .long_help("urls at which the input may be found, e.g. multiple git hosts each with a branch and commit. `{path}` is replaced with each file's percent-encoded path relative to the input directory or tarball."),
+        )
+        .arg(
+            clap::Arg::with_name("url_template")
+            .long("--url-template")
+            .takes_value(true)
+            .multiple(true)
+            .help("url templates at which each input file may be found")
+            .long_help("url templates at which each input file may be found, e.g. https://github.com/postgres/postgres/blob/{ref}/{path}#L{start_line}-L{end_line}. `{start_line}` and `{end_line}` may only appear in the fragment; the first template's per-statement link is stored as each statement's locator.")
+            .validator(validate_url_template),
+        )
+        .arg(
+            clap::Arg::with_name("ref")
+                .long("--ref")
+                .takes_value(true)
+                .help("the git ref substituted for {ref} in url templates"),
+        )
+        .arg(
+            clap::Arg::with_name("id_hash")
+                .long("--id-hash")
+                .takes_value(true)
+                .possible_values(&["xxh3-64", "xxh3-128"])
+                .default_value("xxh3-64")
+                .help("which hashes of each statement to store")
+                .long_help("which hashes of each statement to store. Statement ids are always the xxhash3_64 of the text; xxh3-128 also stores the xxhash3_128 in statements.xxh3_128 to verify that ids are unique."),
         )
         .arg(
             clap::Arg::with_name("pg_version")
                 .long("--pg-version")
                 .takes_value(true)
-                .help("postgres major version"),
+                .help("postgres major version")
+                .long_help("postgres major version to which the input belongs; recorded in the versions table"),
         )
         .arg(
             clap::Arg::with_name("license")
@@ -528,72 +545,239 @@ fn main() -> Result<(), Failure> {
                 .long("--count")
                 .help("print the of count the number of statements"),
         )
+        .subcommand(
+            clap::SubCommand::with_name("migrate")
+                .about("upgrade a corpus db to the current schema version")
+                .arg(
+                    clap::Arg::with_name("db")
+                        .required(true)
+                        .help("the corpus db to upgrade in place")
+                        .validator(validate_corpus_db),
+                )
+                .arg(
+                    clap::Arg::with_name("dry_run")
+                        .long("--dry-run")
+                        .takes_value(false)
+                        .help("print the sql that would be applied without applying it"),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("merge")
+                .about("merge corpus dbs into one")
+                .arg(
+                    clap::Arg::with_name("out")
+                        .long("--out")
+                        .short("-o")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the corpus db to merge into; created if it doesn't exist")
+                        .validator(validate_output_target),
+                )
+                .arg(
+                    clap::Arg::with_name("inputs")
+                        .required(true)
+                        .multiple(true)
+                        .help("the corpus dbs to merge")
+                        .validator(validate_corpus_db),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("export")
+                .about("export a corpus db for loading elsewhere")
+                .arg(
+                    clap::Arg::with_name("format")
+                        .long("--format")
+                        .takes_value(true)
+                        .possible_values(&["pg-copy"])
+                        .default_value("pg-copy")
+                        .help("the export format")
+                        .long_help("the export format. `pg-copy` is a script of CREATE TABLE statements and COPY ... FROM stdin blocks for `psql -f`."),
+                )
+                .arg(
+                    clap::Arg::with_name("out")
+                        .long("--out")
+                        .short("-o")
+                        .takes_value(true)
+                        .default_value("stdout")
+                        .help("where to write the export")
+                        .validator(validate_output_target),
+                )
+                .arg(
+                    clap::Arg::with_name("db")
+                        .required(true)
+                        .help("the corpus db to export")
+                        .validator(validate_corpus_db),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("export-fixtures")
+                .about("write a file-based test suite from a corpus db")
+                .long_about("write versions/NNN/regress/<file>/NNNN/input.sql and expected.json for each statement in each source file ingested with --pg-version NNN into a corpus db. expected.json holds the statement's languages, fingerprint, and oracle predictions.")
+                .arg(
+                    clap::Arg::with_name("out")
+                        .long("--out")
+                        .short("-o")
+                        .takes_value(true)
+                        .default_value("./fixtures")
+                        .help("the directory in which to write fixtures"),
+                )
+                .arg(
+                    clap::Arg::with_name("db")
+                        .required(true)
+                        .help("the corpus db to export")
+                        .validator(validate_corpus_db),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("dedupe-report")
+                .about("list groups of statements sharing a fingerprint")
+                .long_about("list each fingerprint with its number of statements and distinct normalized texts, largest group first, as tab-separated values. Each group's example is the normalized text of its shortest statement.")
+                .arg(
+                    clap::Arg::with_name("min_count")
+                        .long("--min-count")
+                        .takes_value(true)
+                        .default_value("2")
+                        .help("only list fingerprints with at least this many statements")
+                        .validator(validate_count),
+                )
+                .arg(
+                    clap::Arg::with_name("limit")
+                        .long("--limit")
+                        .takes_value(true)
+                        .help("list at most this many fingerprints")
+                        .validator(validate_count),
+                )
+                .arg(
+                    clap::Arg::with_name("db")
+                        .required(true)
+                        .help("the corpus db to report on")
+                        .validator(validate_corpus_db),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("minimize")
+                .about("write a small subset of a corpus db covering all of it")
+                .long_about("greedily select statements until every fingerprint, parse-node type in statement_asts, and language in a corpus db is covered, preferring shorter statements. The selection is written to another corpus db along with its documents, sources, urls, licenses, versions, and predictions.")
+                .arg(
+                    clap::Arg::with_name("out")
+                        .long("--out")
+                        .short("-o")
+                        .takes_value(true)
+                        .required(true)
+                        .help("the corpus db to write to; created if it doesn't exist")
+                        .validator(validate_output_target),
+                )
+                .arg(
+                    clap::Arg::with_name("db")
+                        .required(true)
+                        .help("the corpus db to minimize")
+                        .validator(validate_corpus_db),
+                ),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("repair")
+                .about("re-ingest documents that were only partially written")
+                .long_about("find documents in a corpus db whose rows weren't all written, e.g. by a splitter that crashed partway through, and re-ingest them from --input. A document's old rows are kept unless it's rewritten completely. Takes the same top-level options the documents were first ingested with, e.g. `splitter --input ./src --url-template ... repair corpus.db`.")
+                .arg(
+                    clap::Arg::with_name("db")
+                        .required(true)
+                        .help("the corpus db to repair in place")
+                        .validator(validate_corpus_db),
+                ),
+        )
         .get_matches();
 
-    if matches.is_present("license") && !matches.is_present("spdx") {
-        return Err(Failure::Other(format!(
-            "missing the spdx identifier for {}",
-            matches.value_of("license").unwrap()
-        )));
+    if let Some(matches) = matches.subcommand_matches("minimize") {
+        return minimize(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("dedupe-report") {
+        return dedupe_report(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("export-fixtures") {
+        return export_fixtures(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("export") {
+        return export(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("migrate") {
+        return migrate(matches);
+    }
+    if let Some(matches) = matches.subcommand_matches("merge") {
+        return merge(matches);
     }
-    // read from stdin or a file
-    let mut buffer = String::new();
-    match matches.value_of("input") {
-        None | Some("stdin") => {
-            io::stdin().read_to_string(&mut buffer)?;
-        }
-        Some(filename) => match fs::read_to_string(filename) {
-            Ok(data) => buffer = data,
-            Err(err) => {
-                eprintln!("{:?}", err);
-                process::exit(1);
-            }
-        },
-    };
-    let out = matches.value_of("out");
-    // TODO: validate each URL
-    let mut urls: Vec<&str> = Vec::with_capacity(matches.occurrences_of("url").try_into().unwrap());
-    if let Some(url_args) = matches.values_of("url") {
-        for url in url_args {
-            urls.push(url);
-        }
-    };
-    let document_id = xxh3_64(buffer.as_bytes()) as i64;
 
-    if let Some(output_path) = out {
-        let mut conn = sqlite::connect(output_path)?;
-        let already_processed = doc_already_processed(&mut conn, document_id)?;
-        let spdx = matches.value_of("spdx");
-        if let Some(license_path) = matches.value_of("license") {
-            let license = fs::read_to_string(license_path)?;
-            sqlite::insert_license(&mut conn, spdx.unwrap(), license).unwrap();
-        }
-        let url_ids = sqlite::bulk_insert_urls(&mut conn, urls.as_slice(), spdx).unwrap();
-        if already_processed {
-            sqlite::bulk_insert_document_urls(&mut conn, document_id, url_ids.as_slice())?;
-            return Ok(());
-        }
-        let (statements, statement_fingerprints, statement_languages, sources) = process_doc(
-            buffer.as_str(),
-            document_id,
-            urls.as_slice(),
-            matches.is_present("count"),
-            matches.is_present("debug"),
-        );
+    if let Some(repair_matches) = matches.subcommand_matches("repair") {
+        return repair(&matches, repair_matches);
+    }
 
-        conn.execute(
-            "INSERT INTO documents(id) VALUES (?) ON CONFLICT DO NOTHING",
-            rusqlite::params![document_id as i64],
-        )
-        .unwrap();
-        // TODO: separate inserting statements from statement_languages
-        sqlite::bulk_insert_statements(&mut conn, statements).unwrap();
-        sqlite::bulk_insert_statement_documents(&mut conn, sources).unwrap();
-        sqlite::bulk_insert_statement_fingerprints(&mut conn, statement_fingerprints).unwrap();
-        sqlite::bulk_insert_statement_languages(&mut conn, statement_languages).unwrap();
-        conn.close().unwrap();
+    let documents = read_documents(&matches)?;
+    let splitter = build_splitter(&matches)?;
+    let output_path = matches.value_of("out").unwrap();
+    let format = match matches.value_of("format") {
+        Some(format) => format,
+        None if output_path == "stdout" => "jsonl",
+        None => "sqlite",
+    };
+    if format == "sqlite" && output_path == "stdout" {
+        return Err(Failure::Other(
+            "--format sqlite needs a file to write to via --out".to_string(),
+        ));
+    }
+    let jobs: usize = matches.value_of("jobs").unwrap().parse().unwrap();
+    let do_count = matches.is_present("count");
+    let do_debug = matches.is_present("debug");
+    // keep stdout clean for json lines
+    let mut log: Box<dyn Write> = if output_path == "stdout" {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+    let mut log_error: Option<io::Error> = None;
+    // --debug and --count already report each document, sometimes on stderr
+    let mut progress = Progress::new(
+        documents.len,
+        !(output_path == "stdout" && (do_debug || do_count)),
+    );
+    let report = |path: &str, outcome: Ingested| {
+        progress.update(path, &outcome);
+        match outcome {
+            Ingested::Split(split) => {
+                let mut result = Ok(());
+                if do_debug {
+                    result = result.and_then(|_| print_debug(&mut log, split));
+                }
+                if do_count {
+                    result = result.and_then(|_| print_count(&mut log, path, split));
+                }
+                if let Err(e) = result {
+                    log_error.get_or_insert(e);
+                }
+                if let Some(Failure::Split { line, .. }) = split.split_failure() {
+                    progress.println(&format!("{}: only split up to line {}", path, line));
+                }
+            }
+            Ingested::AlreadyProcessed => {}
+            Ingested::Skipped(e) => progress.println(&format!("{}: {:?}", path, e)),
+        };
+    };
+    if format == "jsonl" {
+        let out: Box<dyn Write> = if output_path == "stdout" {
+            Box::new(io::stdout())
+        } else {
+            Box::new(File::create(output_path)?)
+        };
+        let mut writer = jsonl::JsonlWriter::new(io::BufWriter::new(out));
+        writer::ingest_parallel(&mut writer, &splitter, documents, jobs, report)?;
     } else {
-        println!("no output target")
+        let options = ingest_options(&matches)?;
+        let mut corpus = Corpus::open(output_path)?;
+        corpus.ingest(&splitter, documents, &options, jobs, report)?;
+        corpus.close()?;
+    }
+    progress.finish();
+    progress.write_summary(&mut log)?;
+    if let Some(e) = log_error {
+        return Err(Failure::IoErr(e));
     }
     return Ok(());
 }
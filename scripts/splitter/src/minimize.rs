@@ -0,0 +1,495 @@
+use pg_query_wrapper::pbuf::ParseResult;
+use prost::Message;
+use rusqlite::{types::Value, Connection};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use crate::span::{LineSpan, UnicodeSpan};
+use crate::{
+    sqlite, Failure, IdHash, Language, Statement, StatementAst, StatementChild, StatementSource,
+};
+
+/// how a parse node starts in the Debug output of a decoded parse tree
+const NODE_DEBUG_PREFIX: &[u8] = b"Node { node: Some(";
+
+/// the names of the parse-node types in a protobuf-encoded
+/// `pg_query.ParseResult`, e.g. "SelectStmt". prost has no reflection, but
+/// prost-derive prints every message as `Name { field: value, .. }` and every
+/// oneof variant as `Variant(..)`, so outside of string literals each node of
+/// the decoded tree starts with `Node { node: Some(<type>(`.
+fn node_types(protobuf: &[u8]) -> Result<HashSet<String>, Failure> {
+    let tree = ParseResult::decode(protobuf)
+        .map_err(|e| Failure::Other(format!("malformed pg_query.ParseResult: {}", e)))?;
+    let debug = format!("{:?}", tree);
+    let bytes = debug.as_bytes();
+    let is_ident = |byte: u8| byte.is_ascii_alphanumeric() || byte == b'_';
+    let mut types = HashSet::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'"' {
+            // Debug escapes quotes and backslashes within strings
+            i += 1;
+            while i < bytes.len() && bytes[i] != b'"' {
+                i += if bytes[i] == b'\\' { 2 } else { 1 };
+            }
+            i += 1;
+        } else if bytes[i..].starts_with(NODE_DEBUG_PREFIX) && (i == 0 || !is_ident(bytes[i - 1])) {
+            let start = i + NODE_DEBUG_PREFIX.len();
+            let mut end = start;
+            while end < bytes.len() && is_ident(bytes[end]) {
+                end += 1;
+            }
+            types.insert(debug[start..end].to_string());
+            i = end;
+        } else {
+            i += 1;
+        }
+    }
+    return Ok(types);
+}
+
+/// the fingerprints, parse-node types, and languages a minimal corpus must cover
+#[derive(Default)]
+struct Features {
+    index: HashMap<String, usize>,
+    by_statement: HashMap<i64, Vec<usize>>,
+}
+
+impl Features {
+    fn add(&mut self, statement_id: i64, feature: String) {
+        let n = self.index.len();
+        let feature = *self.index.entry(feature).or_insert(n);
+        let features = self
+            .by_statement
+            .entry(statement_id)
+            .or_insert_with(Vec::new);
+        if !features.contains(&feature) {
+            features.push(feature);
+        }
+    }
+
+    fn collect(conn: &Connection) -> Result<Self, Failure> {
+        let mut features = Features::default();
+        let mut query =
+            conn.prepare("SELECT statement_id, fingerprint FROM statement_fingerprints")?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            let fingerprint: i64 = row.get(1)?;
+            features.add(row.get(0)?, format!("fingerprint:{:x}", fingerprint));
+        }
+        let mut query = conn.prepare(
+            "SELECT sl.statement_id, l.name FROM statement_languages AS sl
+            JOIN languages AS l ON l.id = sl.language_id",
+        )?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            let language: String = row.get(1)?;
+            features.add(row.get(0)?, format!("language:{}", language));
+        }
+        // each version of pg_query.proto numbers its node types differently, so
+        // only trees from this build's version can be decoded
+        let mut query = conn.prepare(
+            "SELECT statement_id, protobuf FROM statement_asts WHERE pg_query_version = ?",
+        )?;
+        let mut rows = query.query([*crate::PG_QUERY_VERSION])?;
+        while let Some(row) = rows.next()? {
+            let protobuf: Vec<u8> = row.get(1)?;
+            for node_type in node_types(protobuf.as_slice())? {
+                features.add(row.get(0)?, format!("node:{}", node_type));
+            }
+        }
+        return Ok(features);
+    }
+}
+
+/// the statements chosen to cover a corpus
+pub struct Cover {
+    pub statement_ids: Vec<i64>,
+    /// the number of distinct fingerprints, parse-node types, and languages
+    pub features: usize,
+}
+
+/// greedily pick the statements covering the most uncovered fingerprints,
+/// parse-node types, and languages until every one is covered, preferring
+/// shorter statements
+pub fn cover(conn: &Connection) -> Result<Cover, Failure> {
+    let features = Features::collect(conn)?;
+    let mut lengths: HashMap<i64, i64> = HashMap::with_capacity(features.by_statement.len());
+    {
+        let mut query = conn.prepare("SELECT id, length(\"text\") FROM statements")?;
+        let mut rows = query.query([])?;
+        while let Some(row) = rows.next()? {
+            lengths.insert(row.get(0)?, row.get(1)?);
+        }
+    }
+    let mut covered = vec![false; features.index.len()];
+    // gains only shrink as features are covered, so a statement whose
+    // recomputed gain is still at the top of the heap is the best pick
+    let mut heap: BinaryHeap<(usize, Reverse<i64>, Reverse<i64>)> = features
+        .by_statement
+        .iter()
+        .map(|(id, f)| {
+            (
+                f.len(),
+                Reverse(*lengths.get(id).unwrap_or(&0)),
+                Reverse(*id),
+            )
+        })
+        .collect();
+    let mut statement_ids: Vec<i64> = Vec::new();
+    while let Some((gain, length, Reverse(id))) = heap.pop() {
+        let statement_features = &features.by_statement[&id];
+        let current = statement_features.iter().filter(|&&f| !covered[f]).count();
+        if current == 0 {
+            continue;
+        }
+        if current < gain {
+            heap.push((current, length, Reverse(id)));
+            continue;
+        }
+        for &f in statement_features.iter() {
+            covered[f] = true;
+        }
+        statement_ids.push(id);
+    }
+    return Ok(Cover {
+        statement_ids,
+        features: features.index.len(),
+    });
+}
+
+fn collect_rows<T, F>(conn: &Connection, sql: &str, f: F) -> Result<Vec<T>, rusqlite::Error>
+where
+    F: FnMut(&rusqlite::Row) -> Result<T, rusqlite::Error>,
+{
+    let mut query = conn.prepare(sql)?;
+    let rows = query.query_map([], f)?;
+    return rows.collect();
+}
+
+/// what was written to the minimized corpus
+pub struct Minimized {
+    pub statements: usize,
+    pub documents: usize,
+    pub features: usize,
+}
+
+/// write a cover of `src` to `dst`, along with the rows the chosen statements
+/// depend on: their documents, sources, urls, licenses, versions, and
+/// predictions
+pub fn minimize(src: &Connection, dst: &mut Connection) -> Result<Minimized, Failure> {
+    let cover = cover(src)?;
+    src.execute_batch(
+        "DROP TABLE IF EXISTS temp.minimized;
+        CREATE TEMP TABLE minimized(statement_id INTEGER PRIMARY KEY);",
+    )?;
+    {
+        let mut insert = src.prepare("INSERT INTO temp.minimized(statement_id) VALUES (?)")?;
+        for id in cover.statement_ids.iter() {
+            insert.execute([id])?;
+        }
+    }
+    let txn = dst.transaction()?;
+
+    let licenses: Vec<(String, String)> = collect_rows(
+        src,
+        "SELECT DISTINCT l.id, l.\"text\" FROM licenses AS l
+        JOIN urls AS u ON u.license_id = l.id
+        JOIN document_urls AS du ON du.url_id = u.id
+        JOIN document_statements AS ds ON ds.document_id = du.document_id
+        JOIN temp.minimized AS m ON m.statement_id = ds.statement_id",
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    for (id, text) in licenses {
+        sqlite::insert_license(&txn, id.as_str(), text)?;
+    }
+
+    let runs: Vec<Vec<Value>> = collect_rows(
+        src,
+        "SELECT r.id, r.splitter_version, r.splitter_commit, r.psql_splitter_commit,
+            r.pg_query_wrapper_commit, r.libpg_query_version, r.command_line, r.input_roots,
+            r.started_at, r.finished_at
+        FROM runs AS r WHERE r.id IN (
+            SELECT d.run_id FROM documents AS d
+            JOIN document_statements AS ds ON ds.document_id = d.id
+            JOIN temp.minimized AS m ON m.statement_id = ds.statement_id
+        )",
+        |row| (0..10).map(|i| row.get::<_, Value>(i)).collect(),
+    )?;
+    for run in runs {
+        txn.execute(
+            "INSERT INTO runs(id, splitter_version, splitter_commit, psql_splitter_commit,
+                pg_query_wrapper_commit, libpg_query_version, command_line, input_roots,
+                started_at, finished_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) ON CONFLICT DO NOTHING",
+            rusqlite::params_from_iter(run.iter()),
+        )?;
+    }
+    let documents: Vec<(i64, Option<String>, Option<i64>, bool)> = collect_rows(
+        src,
+        "SELECT DISTINCT d.id, d.\"encoding\", d.run_id, d.complete FROM documents AS d
+        JOIN document_statements AS ds ON ds.document_id = d.id
+        JOIN temp.minimized AS m ON m.statement_id = ds.statement_id",
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+    )?;
+    for (id, encoding, run_id, complete) in documents.iter() {
+        sqlite::insert_document(&txn, *id, encoding.as_deref(), *run_id, *complete)?;
+    }
+
+    let urls: Vec<(i64, String, Option<String>)> = collect_rows(
+        src,
+        "SELECT du.document_id, u.url, u.license_id FROM document_urls AS du
+        JOIN urls AS u ON u.id = du.url_id
+        WHERE du.document_id IN (
+            SELECT ds.document_id FROM document_statements AS ds
+            JOIN temp.minimized AS m ON m.statement_id = ds.statement_id
+        )
+        ORDER BY du.document_id, u.url",
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    for (document_id, url, license_id) in urls {
+        let url_ids = sqlite::bulk_insert_urls(&txn, &[url.as_str()], license_id.as_deref())?;
+        sqlite::bulk_insert_document_urls(&txn, document_id, url_ids.as_slice())?;
+    }
+
+    let statements: Vec<(String, bool)> = collect_rows(
+        src,
+        "SELECT s.\"text\", s.xxh3_128 IS NOT NULL FROM statements AS s
+        JOIN temp.minimized AS m ON m.statement_id = s.id",
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    // only the id and text of a statement are stored
+    let (wide, narrow): (Vec<(String, bool)>, Vec<(String, bool)>) =
+        statements.into_iter().partition(|(_, wide)| *wide);
+    for (statements, id_hash) in vec![(wide, IdHash::Xxh3_128), (narrow, IdHash::Xxh3_64)] {
+        let statements: Vec<Statement> = statements
+            .into_iter()
+            .map(|(text, _)| Statement::new(text, Language::Other, 0))
+            .collect();
+        sqlite::bulk_insert_statements(&txn, statements.as_slice(), id_hash)?;
+    }
+
+    let languages: Vec<(i64, Language)> = collect_rows(
+        src,
+        "SELECT sl.statement_id, sl.language_id FROM statement_languages AS sl
+        JOIN temp.minimized AS m ON m.statement_id = sl.statement_id",
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?
+    .into_iter()
+    .filter_map(|(id, language_id): (i64, i64)| Language::from_id(language_id).map(|l| (id, l)))
+    .collect();
+    sqlite::bulk_insert_statement_languages(&txn, languages.as_slice())?;
+
+    let sources: Vec<StatementSource> = collect_rows(
+        src,
+        "SELECT ds.document_id, ds.statement_id, ds.start_line, ds.end_line,
+            ds.start_offset, ds.end_offset, ds.locator,
+            ds.start_column, ds.end_column, ds.start_char, ds.end_char,
+            ds.start_utf16, ds.end_utf16
+        FROM document_statements AS ds
+        JOIN temp.minimized AS m ON m.statement_id = ds.statement_id",
+        |row| {
+            let start_column: Option<i64> = row.get(7)?;
+            let unicode = match start_column {
+                Some(start_column) => Some(UnicodeSpan {
+                    start_column: start_column as usize,
+                    end_column: row.get::<_, i64>(8)? as usize,
+                    start_char: row.get::<_, i64>(9)? as usize,
+                    end_char: row.get::<_, i64>(10)? as usize,
+                    start_utf16: row.get::<_, i64>(11)? as usize,
+                    end_utf16: row.get::<_, i64>(12)? as usize,
+                }),
+                None => None,
+            };
+            Ok(StatementSource {
+                document_id: row.get(0)?,
+                statement_id: row.get(1)?,
+                span: LineSpan {
+                    start_line: row.get::<_, i64>(2)? as usize,
+                    end_line: row.get::<_, i64>(3)? as usize,
+                    start_offset: row.get::<_, i64>(4)? as usize,
+                    end_offset: row.get::<_, i64>(5)? as usize,
+                    unicode,
+                },
+                locator: row.get(6)?,
+            })
+        },
+    )?;
+    sqlite::bulk_insert_statement_documents(&txn, sources.as_slice())?;
+
+    let children: Vec<StatementChild> = collect_rows(
+        src,
+        "SELECT c.parent_id, c.child_id, c.start_offset, c.end_offset FROM statement_children AS c
+        JOIN temp.minimized AS parent ON parent.statement_id = c.parent_id
+        JOIN temp.minimized AS child ON child.statement_id = c.child_id",
+        |row| {
+            Ok(StatementChild {
+                parent_id: row.get(0)?,
+                child_id: row.get(1)?,
+                start_offset: row.get::<_, Option<i64>>(2)?.map(|offset| offset as usize),
+                end_offset: row.get::<_, Option<i64>>(3)?.map(|offset| offset as usize),
+            })
+        },
+    )?;
+    sqlite::bulk_insert_statement_children(&txn, children.as_slice())?;
+
+    let fingerprints: Vec<(i64, i64)> = collect_rows(
+        src,
+        "SELECT f.statement_id, f.fingerprint FROM statement_fingerprints AS f
+        JOIN temp.minimized AS m ON m.statement_id = f.statement_id",
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    sqlite::bulk_insert_statement_fingerprints(&txn, fingerprints.as_slice())?;
+
+    let asts: Vec<StatementAst> = collect_rows(
+        src,
+        "SELECT a.statement_id, a.pg_query_version, a.protobuf FROM statement_asts AS a
+        JOIN temp.minimized AS m ON m.statement_id = a.statement_id",
+        |row| {
+            Ok(StatementAst {
+                statement_id: row.get(0)?,
+                pg_query_version: row.get(1)?,
+                protobuf: row.get(2)?,
+            })
+        },
+    )?;
+    sqlite::bulk_insert_statement_asts(&txn, asts.as_slice())?;
+
+    let normalized: Vec<(i64, String)> = collect_rows(
+        src,
+        "SELECT n.statement_id, n.normalized FROM statement_normalized AS n
+        JOIN temp.minimized AS m ON m.statement_id = n.statement_id",
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    sqlite::bulk_insert_statement_normalized(&txn, normalized.as_slice())?;
+
+    let statement_versions: Vec<(String, String, i64)> = collect_rows(
+        src,
+        "SELECT v.family, v.\"version\", sv.statement_id FROM statement_versions AS sv
+        JOIN versions AS v ON v.id = sv.version_id
+        JOIN temp.minimized AS m ON m.statement_id = sv.statement_id
+        ORDER BY v.family, v.\"version\"",
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    let mut by_version: HashMap<(String, String), Vec<i64>> = HashMap::new();
+    for (family, version, statement_id) in statement_versions {
+        by_version
+            .entry((family, version))
+            .or_insert_with(Vec::new)
+            .push(statement_id);
+    }
+    for ((family, version), statement_ids) in by_version.iter() {
+        let version_id = sqlite::insert_version(&txn, family, version)?;
+        sqlite::bulk_insert_statement_versions(&txn, statement_ids.as_slice(), version_id)?;
+    }
+    let language_versions: Vec<(String, String, i64)> = collect_rows(
+        src,
+        "SELECT DISTINCT v.family, v.\"version\", lv.language_id FROM language_versions AS lv
+        JOIN versions AS v ON v.id = lv.version_id
+        JOIN statement_languages AS sl ON sl.language_id = lv.language_id
+        JOIN temp.minimized AS m ON m.statement_id = sl.statement_id",
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    for (family, version, language_id) in language_versions {
+        let version_id = sqlite::insert_version(&txn, family.as_str(), version.as_str())?;
+        sqlite::bulk_insert_language_versions(&txn, &[language_id], version_id)?;
+    }
+    let document_versions: Vec<(String, String, i64)> = collect_rows(
+        src,
+        "SELECT v.family, v.\"version\", dv.document_id FROM document_versions AS dv
+        JOIN versions AS v ON v.id = dv.version_id
+        WHERE dv.document_id IN (
+            SELECT ds.document_id FROM document_statements AS ds
+            JOIN temp.minimized AS m ON m.statement_id = ds.statement_id
+        )",
+        |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+    )?;
+    for (family, version, document_id) in document_versions {
+        let version_id = sqlite::insert_version(&txn, family.as_str(), version.as_str())?;
+        sqlite::insert_document_version(&txn, document_id, version_id)?;
+    }
+
+    // predictions come from every oracle, not just the splitter's, so they're
+    // copied verbatim
+    let oracles: Vec<(i64, Option<String>)> = collect_rows(
+        src,
+        "SELECT o.id, o.name FROM oracles AS o WHERE o.id IN (
+            SELECT p.oracle_id FROM predictions AS p
+            JOIN temp.minimized AS m ON m.statement_id = p.statement_id
+        )",
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )?;
+    for (id, name) in oracles {
+        txn.execute(
+            "INSERT INTO oracles(id, name) VALUES (?, ?) ON CONFLICT DO NOTHING",
+            rusqlite::params![id, name],
+        )?;
+    }
+    let predictions: Vec<Vec<Value>> = collect_rows(
+        src,
+        "SELECT p.statement_id, p.oracle_id, p.language_id, p.error, p.\"message\", p.valid
+        FROM predictions AS p
+        JOIN temp.minimized AS m ON m.statement_id = p.statement_id",
+        |row| (0..6).map(|i| row.get::<_, Value>(i)).collect(),
+    )?;
+    {
+        let mut insert = txn.prepare(
+            "INSERT INTO predictions(statement_id, oracle_id, language_id, error, \"message\", valid)
+            VALUES (?, ?, ?, ?, ?, ?) ON CONFLICT DO NOTHING",
+        )?;
+        for prediction in predictions {
+            insert.execute(rusqlite::params_from_iter(prediction.iter()))?;
+        }
+    }
+
+    txn.commit()?;
+    src.execute_batch("DROP TABLE temp.minimized;")?;
+    return Ok(Minimized {
+        statements: cover.statement_ids.len(),
+        documents: documents.len(),
+        features: cover.features,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn types(sql: &str) -> Vec<String> {
+        let tree = pg_query_wrapper::parse_to_protobuf(sql).unwrap();
+        let mut protobuf: Vec<u8> = Vec::with_capacity(tree.encoded_len());
+        tree.encode(&mut protobuf).unwrap();
+        let mut types: Vec<String> = node_types(protobuf.as_slice())
+            .unwrap()
+            .into_iter()
+            .collect();
+        types.sort();
+        return types;
+    }
+
+    #[test]
+    fn finds_node_types() {
+        assert_eq!(
+            types("select 1"),
+            ["AConst", "Integer", "ResTarget", "SelectStmt"]
+        );
+        assert_eq!(
+            types("select 1 from t"),
+            ["AConst", "Integer", "RangeVar", "ResTarget", "SelectStmt"]
+        );
+        assert!(types("").is_empty());
+    }
+
+    #[test]
+    fn ignores_node_types_in_strings() {
+        assert_eq!(
+            types("select 'Node { node: Some(Fake(\\\" ) Node { node: Some(Fake('"),
+            ["AConst", "ResTarget", "SelectStmt", "String"]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_trees() {
+        assert!(node_types(&[0xff]).is_err());
+    }
+}
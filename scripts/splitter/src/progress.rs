@@ -0,0 +1,139 @@
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+use std::time::Instant;
+
+use crate::{Failure, Ingested, Language};
+
+/// what was split out of an ingest in one language
+#[derive(Default)]
+struct LanguageSummary {
+    statements: usize,
+    unique: HashSet<i64>,
+    /// `PgSql` statements pg_query couldn't parse
+    invalid: usize,
+}
+
+/// a progress bar on stderr plus running totals for a summary. The bar hides
+/// itself if stderr isn't a terminal.
+pub struct Progress {
+    bar: ProgressBar,
+    started: Instant,
+    statements: usize,
+    already_processed: usize,
+    errors: usize,
+    partial: usize,
+    languages: BTreeMap<i64, LanguageSummary>,
+}
+
+impl Progress {
+    /// `show` is false to hide the bar regardless, e.g. if something else is
+    /// writing to stderr
+    pub fn new(documents: usize, show: bool) -> Self {
+        let target = if show {
+            ProgressDrawTarget::stderr()
+        } else {
+            ProgressDrawTarget::hidden()
+        };
+        let bar = ProgressBar::with_draw_target(documents as u64, target);
+        bar.set_style(
+            ProgressStyle::default_bar()
+                .template("{elapsed_precise} [{bar:30}] {pos}/{len} documents {wide_msg}")
+                .progress_chars("=> "),
+        );
+        return Progress {
+            bar,
+            started: Instant::now(),
+            statements: 0,
+            already_processed: 0,
+            errors: 0,
+            partial: 0,
+            languages: BTreeMap::new(),
+        };
+    }
+
+    /// print a line to stderr without garbling the bar
+    pub fn println(&self, line: &str) {
+        if self.bar.is_hidden() {
+            eprintln!("{}", line);
+        } else {
+            self.bar.println(line);
+        }
+    }
+
+    /// count a document's outcome and show it as the current file
+    pub fn update(&mut self, path: &str, outcome: &Ingested) {
+        match outcome {
+            Ingested::Split(split) => {
+                for statement in split.statements() {
+                    let summary = self.languages.entry(statement.language().id()).or_default();
+                    summary.statements += 1;
+                    summary.unique.insert(statement.id());
+                }
+                let invalid = split.predictions().iter().filter(|p| !p.valid).count();
+                if invalid > 0 {
+                    self.languages
+                        .entry(Language::PgSql.id())
+                        .or_default()
+                        .invalid += invalid;
+                }
+                self.statements += split.statements().len();
+                if let Some(Failure::Split { .. }) = split.split_failure() {
+                    self.partial += 1;
+                }
+            }
+            Ingested::AlreadyProcessed => self.already_processed += 1,
+            Ingested::Skipped(_) => self.errors += 1,
+        }
+        let seconds = self.started.elapsed().as_secs_f64();
+        let per_second = if seconds > 0.0 {
+            self.statements as f64 / seconds
+        } else {
+            0.0
+        };
+        self.bar.set_message(format!(
+            "{} statements ({:.0}/s), {} errors {}",
+            self.statements, per_second, self.errors, path
+        ));
+        self.bar.inc(1);
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+
+    /// a table of statements per language, followed by document totals
+    pub fn write_summary(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "{:10} {:>10} {:>10} {:>16}",
+            "language", "statements", "unique", "pg_query errors"
+        )?;
+        for (id, summary) in self.languages.iter() {
+            let name = Language::from_id(*id).map_or("?", |language| language.name());
+            let invalid = if *id == Language::PgSql.id() {
+                summary.invalid.to_string()
+            } else {
+                "-".to_string()
+            };
+            writeln!(
+                out,
+                "{:10} {:>10} {:>10} {:>16}",
+                name,
+                summary.statements,
+                summary.unique.len(),
+                invalid
+            )?;
+        }
+        let split = self.bar.position() as usize - self.already_processed - self.errors;
+        return writeln!(
+            out,
+            "{} documents split ({} partially), {} already processed, {} skipped in {:.1}s",
+            split,
+            self.partial,
+            self.already_processed,
+            self.errors,
+            self.started.elapsed().as_secs_f64()
+        );
+    }
+}
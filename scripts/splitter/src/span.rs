@@ -0,0 +1,316 @@
+use serde::Serialize;
+
+use crate::encoding::DecodedDocument;
+
+/// a position within a document's utf-8 text
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    /// 1-indexed
+    pub line: usize,
+    /// the utf-8 byte offset
+    pub offset: usize,
+    /// only tracked if asked for, since it means visiting every char
+    pub unicode: Option<UnicodeCursor>,
+}
+
+/// the parts of a position that count chars or utf-16 code units
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UnicodeCursor {
+    /// 1-indexed, in chars
+    pub column: usize,
+    /// the offset in chars, i.e. unicode code points
+    pub char_offset: usize,
+    /// the offset in utf-16 code units, as used by the language server protocol
+    pub utf16_offset: usize,
+}
+
+impl Cursor {
+    /// the start of a document
+    pub fn start(unicode: bool) -> Self {
+        Cursor {
+            line: 1,
+            offset: 0,
+            unicode: if unicode {
+                Some(UnicodeCursor {
+                    column: 1,
+                    char_offset: 0,
+                    utf16_offset: 0,
+                })
+            } else {
+                None
+            },
+        }
+    }
+
+    /// the positions of the last char of `text` (if any) and of whatever
+    /// follows `text`, if `text` starts here
+    fn advance(&self, text: &str) -> (Option<Cursor>, Cursor) {
+        if self.unicode.is_none() {
+            let last = text.char_indices().next_back().map(|(i, _)| Cursor {
+                line: self.line + text[..i].matches('\n').count(),
+                offset: self.offset + i,
+                unicode: None,
+            });
+            let end = Cursor {
+                line: self.line + text.matches('\n').count(),
+                offset: self.offset + text.len(),
+                unicode: None,
+            };
+            return (last, end);
+        }
+        let mut here = *self;
+        let mut last: Option<Cursor> = None;
+        for c in text.chars() {
+            last = Some(here);
+            here.offset += c.len_utf8();
+            let unicode = here.unicode.as_mut().unwrap();
+            unicode.char_offset += 1;
+            unicode.utf16_offset += c.len_utf16();
+            if c == '\n' {
+                here.line += 1;
+                unicode.column = 1;
+            } else {
+                unicode.column += 1;
+            }
+        }
+        return (last, here);
+    }
+
+    /// the position after `text`, if `text` starts here
+    pub fn after(&self, text: &str) -> Cursor {
+        return self.advance(text).1;
+    }
+}
+
+/// where a statement is within a document
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct LineSpan {
+    /// the 1-indexed line of the first char
+    pub start_line: usize,
+    /// the 1-indexed line of the last char. For an empty statement, the
+    /// `start_line`.
+    pub end_line: usize,
+    /// the 0-indexed offset in bytes of the original file, **not** unicode code points
+    pub start_offset: usize,
+    /// the offset in bytes of the original file just past the last char
+    pub end_offset: usize,
+    #[serde(flatten)]
+    pub unicode: Option<UnicodeSpan>,
+}
+
+/// the columns and char-based offsets of a statement
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct UnicodeSpan {
+    /// the 1-indexed column of the first char, in chars
+    pub start_column: usize,
+    /// the 1-indexed column of the last char, in chars. For an empty statement,
+    /// `start_column - 1`.
+    pub end_column: usize,
+    /// the 0-indexed offset in chars, which is the same for the original file
+    /// and its utf-8 transcoding
+    pub start_char: usize,
+    pub end_char: usize,
+    /// the 0-indexed offset in utf-16 code units
+    pub start_utf16: usize,
+    pub end_utf16: usize,
+}
+
+impl LineSpan {
+    /// the span of `text` if it starts at `start` within `doc`. Returns the
+    /// position following `text`.
+    pub fn new(doc: &DecodedDocument, start: Cursor, text: &str) -> (Self, Cursor) {
+        let (last, end) = start.advance(text);
+        debug_assert_eq!(&doc.text[start.offset..end.offset], text);
+        let unicode = match (start.unicode, end.unicode) {
+            (Some(first), Some(after)) => Some(UnicodeSpan {
+                start_column: first.column,
+                end_column: last.map_or(first.column - 1, |c| c.unicode.unwrap().column),
+                start_char: first.char_offset,
+                end_char: after.char_offset,
+                start_utf16: first.utf16_offset,
+                end_utf16: after.utf16_offset,
+            }),
+            _ => None,
+        };
+        let span = LineSpan {
+            start_line: start.line,
+            end_line: last.map_or(start.line, |c| c.line),
+            start_offset: doc.original_offset(start.offset),
+            end_offset: doc.original_offset(end.offset),
+            unicode,
+        };
+        return (span, end);
+    }
+
+    /// the 1-indexed inclusive line range, e.g. for `#L{start}-L{end}` links
+    pub fn lines(&self) -> (usize, usize) {
+        return (self.start_line, self.end_line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding;
+    use crate::input::InputDocument;
+    use crate::Splitter;
+    use encoding_rs::{Encoding, EUC_JP, UTF_8};
+    use std::collections::HashMap;
+
+    const DOCUMENTS: [&str; 6] = [
+        "select 1;\nselect 2;\n",
+        "select 1;\nselect 2;",
+        "select 1;\r\nselect 2;\r\n",
+        "select 'é';\n-- 日本\nselect '😀', 'x';",
+        "\n\nselect 1;\n\n",
+        "",
+    ];
+
+    /// every way of cutting `text` into three consecutive pieces at char
+    /// boundaries
+    fn cuts(text: &str) -> Vec<[&str; 3]> {
+        let boundaries: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(text.len()))
+            .collect();
+        let mut cuts = vec![];
+        for (n, &i) in boundaries.iter().enumerate() {
+            for &j in boundaries[n..].iter() {
+                cuts.push([&text[..i], &text[i..j], &text[j..]]);
+            }
+        }
+        return cuts;
+    }
+
+    /// check the span of each of `pieces`, which make up `doc`, against
+    /// `original`, the bytes `doc` was decoded from
+    fn check_spans(original: &[u8], encoding: &'static Encoding, pieces: &[&str]) {
+        let doc = encoding::decode(original.to_vec(), Some(encoding)).unwrap();
+        let text = doc.text.as_str();
+        let utf16: Vec<u16> = text.encode_utf16().collect();
+        let mut cursor = Cursor::start(true);
+        let mut bytes_only = Cursor::start(false);
+        let mut offset = 0;
+        for piece in pieces {
+            let (span, next) = LineSpan::new(&doc, cursor, piece);
+            let (bytes_span, bytes_next) = LineSpan::new(&doc, bytes_only, piece);
+
+            let (decoded, _) =
+                encoding.decode_without_bom_handling(&original[span.start_offset..span.end_offset]);
+            assert_eq!(decoded, *piece, "bytes of {:?}", piece);
+            let unicode = span.unicode.as_ref().unwrap();
+            let chars: String = text
+                .chars()
+                .skip(unicode.start_char)
+                .take(unicode.end_char - unicode.start_char)
+                .collect();
+            assert_eq!(chars, *piece, "chars of {:?}", piece);
+            let piece_utf16: Vec<u16> = piece.encode_utf16().collect();
+            assert_eq!(
+                utf16[unicode.start_utf16..unicode.end_utf16],
+                piece_utf16[..],
+                "utf-16 of {:?}",
+                piece
+            );
+
+            let before = &text[..offset];
+            let start_line = 1 + before.matches('\n').count();
+            let end_line = match piece.char_indices().next_back() {
+                Some((last, _)) => start_line + piece[..last].matches('\n').count(),
+                None => start_line,
+            };
+            assert_eq!(span.lines(), (start_line, end_line), "lines of {:?}", piece);
+            let start_column = 1 + before.chars().rev().take_while(|c| *c != '\n').count();
+            assert_eq!(unicode.start_column, start_column, "column of {:?}", piece);
+
+            assert_eq!(
+                (bytes_span.start_line, bytes_span.end_line),
+                (span.start_line, span.end_line)
+            );
+            assert_eq!(
+                (bytes_span.start_offset, bytes_span.end_offset),
+                (span.start_offset, span.end_offset)
+            );
+            assert_eq!(bytes_span.unicode, None);
+            cursor = next;
+            bytes_only = bytes_next;
+            offset += piece.len();
+        }
+        assert_eq!(cursor.offset, text.len());
+        assert_eq!(
+            bytes_only,
+            Cursor {
+                unicode: None,
+                ..cursor
+            }
+        );
+    }
+
+    #[test]
+    fn spans_reslice_utf8_documents() {
+        for text in DOCUMENTS.iter() {
+            for pieces in cuts(text) {
+                check_spans(text.as_bytes(), UTF_8, &pieces);
+            }
+        }
+    }
+
+    #[test]
+    fn spans_reslice_transcoded_documents() {
+        for text in ["select '日本';\r\nselect 1;\n", "-- 日\nselect 'ア'"].iter() {
+            let (original, _, unmappable) = EUC_JP.encode(text);
+            assert!(!unmappable);
+            for pieces in cuts(text) {
+                check_spans(&original, EUC_JP, &pieces);
+            }
+        }
+    }
+
+    #[test]
+    fn end_line_is_inclusive() {
+        let doc = encoding::decode(b"a;\nb;\n\nc;".to_vec(), None).unwrap();
+        let mut cursor = Cursor::start(false);
+        let mut lines = vec![];
+        for piece in ["a;", "\nb;\n", "\n", "c;", ""].iter() {
+            let (span, next) = LineSpan::new(&doc, cursor, piece);
+            lines.push(span.lines());
+            cursor = next;
+        }
+        assert_eq!(lines, vec![(1, 1), (1, 2), (3, 3), (4, 4), (4, 4)]);
+    }
+
+    #[test]
+    fn split_sources_reslice_statements() {
+        let splitter = Splitter {
+            unicode_positions: true,
+            ..Default::default()
+        };
+        for text in DOCUMENTS.iter() {
+            let doc = splitter
+                .decode(InputDocument {
+                    path: "test.sql".to_string(),
+                    bytes: text.as_bytes().to_vec(),
+                })
+                .unwrap();
+            let split = splitter.split(&doc).unwrap();
+            let statements: HashMap<i64, &str> = split
+                .statements()
+                .iter()
+                .map(|s| (s.id(), s.text()))
+                .collect();
+            let utf16: Vec<u16> = text.encode_utf16().collect();
+            for src in split.sources() {
+                let span = src.span();
+                let statement = statements[&src.statement_id()];
+                assert_eq!(&text[span.start_offset..span.end_offset], statement);
+                let unicode = span.unicode.as_ref().unwrap();
+                let statement_utf16: Vec<u16> = statement.encode_utf16().collect();
+                assert_eq!(
+                    utf16[unicode.start_utf16..unicode.end_utf16],
+                    statement_utf16[..]
+                );
+            }
+        }
+    }
+}
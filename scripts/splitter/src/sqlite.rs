@@ -1,7 +1,182 @@
-use rusqlite::Connection;
+use rusqlite::{types::Value, Connection, ToSql, Transaction};
+use std::collections::HashSet;
 use std::path::PathBuf;
 
-use crate::{Failure, Language, Statement, StatementSource};
+use crate::{
+    writer::CorpusWriter, Document, Failure, IdHash, IngestOptions, Language, Prediction,
+    Statement, StatementAst, StatementChild, StatementSource, PG_FAMILY,
+};
+
+/// the schema version written by `schema.sql`
+pub const SCHEMA_VERSION: (u32, u32) = (0, 12);
+
+/// the sql to upgrade a corpus db from one schema version to the next
+pub struct Migration {
+    pub from: (u32, u32),
+    pub to: (u32, u32),
+    pub sql: &'static str,
+}
+
+/// every migration, in order. Each one's `to` is the next one's `from`; the
+/// last one's `to` is `SCHEMA_VERSION`.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: (0, 0),
+        to: (0, 1),
+        sql: "CREATE TABLE statement_children(
+    parent_id INTEGER REFERENCES statements(id)
+  , child_id INTEGER REFERENCES statements(id)
+  , start_offset INTEGER
+  , end_offset INTEGER
+  , CONSTRAINT statement_children_pkey PRIMARY KEY (parent_id, child_id)
+);
+CREATE INDEX statement_parents ON statement_children(child_id, parent_id);",
+    },
+    Migration {
+        from: (0, 1),
+        to: (0, 2),
+        // documents could only be utf-8 before --encoding
+        sql: "ALTER TABLE documents ADD COLUMN \"encoding\" TEXT;
+UPDATE documents SET \"encoding\" = 'UTF-8';",
+    },
+    Migration {
+        from: (0, 2),
+        to: (0, 3),
+        sql: "CREATE TABLE split_failures (
+    document_id INTEGER PRIMARY KEY REFERENCES documents(id)
+  , start_offset INTEGER
+  , start_line INTEGER
+  , remainder_preview TEXT
+);",
+    },
+    Migration {
+        from: (0, 3),
+        to: (0, 4),
+        sql: "ALTER TABLE statements ADD COLUMN xxh3_128 BLOB;",
+    },
+    Migration {
+        from: (0, 4),
+        to: (0, 5),
+        sql: "ALTER TABLE document_statements ADD COLUMN start_column INTEGER;
+ALTER TABLE document_statements ADD COLUMN end_column INTEGER;
+ALTER TABLE document_statements ADD COLUMN start_char INTEGER;
+ALTER TABLE document_statements ADD COLUMN end_char INTEGER;
+ALTER TABLE document_statements ADD COLUMN start_utf16 INTEGER;
+ALTER TABLE document_statements ADD COLUMN end_utf16 INTEGER;",
+    },
+    Migration {
+        from: (0, 5),
+        to: (0, 6),
+        sql: "CREATE TABLE statement_asts(
+    statement_id INTEGER REFERENCES statements(id)
+  , pg_query_version INTEGER
+  , protobuf BLOB NOT NULL
+  , CONSTRAINT statement_asts_pkey PRIMARY KEY (statement_id, pg_query_version)
+);",
+    },
+    Migration {
+        from: (0, 6),
+        to: (0, 7),
+        sql: "CREATE TABLE statement_normalized(
+    statement_id INTEGER PRIMARY KEY REFERENCES statements(id)
+  , normalized TEXT NOT NULL
+);
+CREATE INDEX normalized_statements ON statement_normalized(normalized, statement_id);",
+    },
+    Migration {
+        from: (0, 7),
+        to: (0, 8),
+        // documents from before runs were tracked have no run, so they're stale
+        sql: "CREATE TABLE runs(
+    id INTEGER PRIMARY KEY
+  , splitter_version TEXT
+  , psql_splitter_commit TEXT
+  , pg_query_wrapper_commit TEXT
+  , libpg_query_version TEXT
+  , command_line TEXT
+  , input_roots TEXT
+  , started_at TEXT
+  , finished_at TEXT
+);
+ALTER TABLE documents ADD COLUMN run_id INTEGER REFERENCES runs(id);",
+    },
+    Migration {
+        from: (0, 8),
+        to: (0, 9),
+        // end_line used to be start_line plus the number of newlines in the
+        // statement, which is one past the statement's last line when it ends
+        // in a newline. Rows written since end_line became inclusive never
+        // match that sum for such statements, so only old rows are touched.
+        // Locators built from `#L{start_line}-L{end_line}` are fixed up too.
+        sql: "UPDATE document_statements
+SET end_line = end_line - 1
+  , locator = CASE
+      WHEN locator LIKE '%-L' || end_line
+        THEN substr(locator, 1, length(locator) - length(end_line)) || (end_line - 1)
+      ELSE locator
+    END
+WHERE statement_id IN (
+    SELECT id FROM statements WHERE substr(\"text\", -1) = char(10)
+  )
+  AND end_line = start_line + (
+    SELECT length(s.\"text\") - length(replace(s.\"text\", char(10), ''))
+    FROM statements AS s
+    WHERE s.id = statement_id
+  );",
+    },
+    Migration {
+        from: (0, 9),
+        to: (0, 10),
+        // before documents were linked to versions, a document was taken to
+        // belong to every version that all of its statements belong to
+        sql: "CREATE TABLE document_versions(
+    document_id INTEGER REFERENCES documents(id)
+  , version_id INTEGER REFERENCES versions(id)
+  , CONSTRAINT document_versions_pkey PRIMARY KEY (document_id, version_id)
+);
+CREATE INDEX version_documents ON document_versions(version_id, document_id);
+INSERT INTO document_versions(document_id, version_id)
+SELECT d.id, v.id FROM documents AS d, versions AS v
+WHERE EXISTS (SELECT 1 FROM document_statements AS ds WHERE ds.document_id = d.id)
+  AND NOT EXISTS (
+    SELECT 1 FROM document_statements AS ds
+    LEFT JOIN statement_versions AS sv
+      ON sv.statement_id = ds.statement_id AND sv.version_id = v.id
+    WHERE ds.document_id = d.id AND sv.statement_id IS NULL
+  );",
+    },
+    Migration {
+        from: (0, 10),
+        to: (0, 11),
+        // a document used to be taken as complete if all of its sources'
+        // statements and language hints were written. Older splitters wrote
+        // the document's row first, so one without sources or a split failure
+        // may have crashed before writing them; repairing an empty document
+        // is harmless.
+        sql: "ALTER TABLE documents ADD COLUMN complete BOOLEAN NOT NULL DEFAULT 0;
+UPDATE documents AS d SET complete = 1
+WHERE NOT EXISTS (
+    SELECT 1 FROM document_statements AS ds
+    LEFT JOIN statements AS s ON s.id = ds.statement_id
+    WHERE ds.document_id = d.id AND (
+      s.id IS NULL
+      OR NOT EXISTS (
+        SELECT 1 FROM statement_languages AS sl WHERE sl.statement_id = ds.statement_id
+      )
+    )
+  )
+  AND (
+    EXISTS (SELECT 1 FROM document_statements AS ds WHERE ds.document_id = d.id)
+    OR EXISTS (SELECT 1 FROM split_failures AS f WHERE f.document_id = d.id)
+  );",
+    },
+    Migration {
+        from: (0, 11),
+        to: (0, 12),
+        // earlier runs' commits are unknown, so their documents are stale
+        sql: "ALTER TABLE runs ADD COLUMN splitter_commit TEXT;",
+    },
+];
 
 /// connect or else.
 pub fn connect(path: &str) -> Result<Connection, Failure> {
@@ -9,24 +184,13 @@ pub fn connect(path: &str) -> Result<Connection, Failure> {
     // else: chuck stuff in :memory:
     let output_path = PathBuf::from(path);
     if !output_path.exists() {
-        println!("initializing {}", path);
+        eprintln!("initializing {}", path);
         let mut conn = Connection::open(path)?;
         init(&mut conn)?; // try to initialize the schema
         return Ok(conn); // return Err(format!("output path {} does not exist", path).to_string());
     } else if output_path.is_file() {
         let conn = Connection::open(path)?;
-        // check the schema version
-        let version: (u32, u32) =
-            conn.query_row("select major, minor from schema_version;", [], |row| {
-                Ok((row.get(0).unwrap(), row.get(1).unwrap()))
-            })?;
-        assert_eq!(
-            version,
-            (0, 0),
-            "unexpected version: got {}.{}, wanted 1",
-            version.0,
-            version.1
-        );
+        check_version(schema_version(&conn)?)?;
         return Ok(conn);
     } else {
         return Err(Failure::Other(format!("non-file path: {}", path)));
@@ -39,24 +203,226 @@ pub fn init(conn: &mut Connection) -> Result<&mut Connection, rusqlite::Error> {
     return Ok(conn);
 }
 
-pub fn bulk_insert_statements(
-    conn: &mut Connection,
-    statements: Vec<Statement>,
-) -> Result<(), rusqlite::Error> {
+/// the latest version recorded in `schema_version`
+pub fn schema_version(conn: &Connection) -> Result<(u32, u32), rusqlite::Error> {
+    return attached_schema_version(conn, "main");
+}
+
+fn attached_schema_version(conn: &Connection, schema: &str) -> Result<(u32, u32), rusqlite::Error> {
+    return conn.query_row(
+        format!(
+            "select major, minor from {}.schema_version order by major desc, minor desc limit 1;",
+            schema
+        )
+        .as_str(),
+        [],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    );
+}
+
+/// whether a db at `version` can be read from and written to. Newer minor
+/// versions only add tables and columns, so they're safe to use.
+fn check_version(version: (u32, u32)) -> Result<(), Failure> {
+    if version.0 != SCHEMA_VERSION.0 || version.1 < SCHEMA_VERSION.1 {
+        return Err(Failure::SchemaVersion {
+            found: version,
+            wanted: SCHEMA_VERSION,
+        });
+    }
+    return Ok(());
+}
+
+/// the migrations needed to bring a db at `version` up to `SCHEMA_VERSION`.
+/// Refuses to downgrade a db with a newer major version.
+pub fn pending_migrations(version: (u32, u32)) -> Result<Vec<&'static Migration>, Failure> {
+    if version.0 > SCHEMA_VERSION.0 {
+        return Err(Failure::SchemaVersion {
+            found: version,
+            wanted: SCHEMA_VERSION,
+        });
+    }
+    let pending: Vec<&'static Migration> =
+        MIGRATIONS.iter().filter(|m| m.from >= version).collect();
+    if let Some(first) = pending.first() {
+        if first.from != version {
+            return Err(Failure::Other(format!(
+                "no migration from schema version {}.{}",
+                version.0, version.1
+            )));
+        }
+    }
+    return Ok(pending);
+}
+
+/// upgrade a db to `SCHEMA_VERSION` in a single transaction, returning the
+/// migrations applied
+pub fn migrate(conn: &mut Connection) -> Result<Vec<&'static Migration>, Failure> {
     let txn = conn.transaction()?;
-    {
-        // block required for lifetime of borrow of txn
-        let insert = &mut txn.prepare(
-            "INSERT INTO statements (id, text) VALUES (?, ?) ON CONFLICT(id) DO NOTHING",
+    let pending = pending_migrations(schema_version(&txn)?)?;
+    for migration in pending.iter() {
+        txn.execute_batch(migration.sql)?;
+        txn.execute(
+            "INSERT INTO schema_version(major, minor) VALUES (?, ?)",
+            [migration.to.0, migration.to.1],
         )?;
-        for statement in statements {
-            insert.execute(rusqlite::params![statement.id as i64, statement.text])?;
+    }
+    txn.commit()?;
+    return Ok(pending);
+}
+
+// the insert functions below don't open transactions of their own: callers
+// wrap a whole run's worth of writes in one.
+
+/// the most `?` parameters sqlite allows in one statement by default before
+/// 3.32.0 (`SQLITE_MAX_VARIABLE_NUMBER`)
+pub const MAX_VARIABLES: usize = 999;
+
+/// insert rows of `n_columns` values each, `params` holding every row's values
+/// in order, e.g. `batch_insert(conn, "INSERT INTO t(a, b)", 2, &[a1, b1, a2, b2])`.
+/// Rows that conflict with existing rows are skipped. Rows are inserted in
+/// chunks of up to `MAX_VARIABLES` parameters through cached statements, so
+/// the full-size chunk's sql is only prepared once per connection.
+pub fn batch_insert<P: ToSql>(
+    conn: &Connection,
+    insert_into: &str,
+    n_columns: usize,
+    params: &[P],
+) -> Result<(), rusqlite::Error> {
+    debug_assert_eq!(params.len() % n_columns, 0);
+    let row = format!(",({})", ",?".repeat(n_columns).trim_start_matches(','));
+    let rows_per_chunk = MAX_VARIABLES / n_columns;
+    for chunk in params.chunks(rows_per_chunk * n_columns) {
+        let sql = format!(
+            "{} VALUES {} ON CONFLICT DO NOTHING",
+            insert_into,
+            row.repeat(chunk.len() / n_columns).trim_start_matches(',')
+        );
+        let mut insert = conn.prepare_cached(sql.as_str())?;
+        insert.execute(rusqlite::params_from_iter(chunk.iter()))?;
+    }
+    return Ok(());
+}
+
+/// insert statements, checking that any already-present id belongs to the same
+/// text. With `IdHash::Xxh3_128`, the xxhash3_128 of each text is stored too.
+pub fn bulk_insert_statements(
+    conn: &Connection,
+    statements: &[Statement],
+    id_hash: IdHash,
+) -> Result<(), Failure> {
+    use xxhash_rust::xxh3::xxh3_128;
+    let insert = &mut conn.prepare_cached(
+        "INSERT INTO statements (id, text, xxh3_128) VALUES (?, ?, ?) ON CONFLICT(id) DO NOTHING",
+    )?;
+    let existing = &mut conn.prepare_cached("SELECT text FROM statements WHERE id = ?")?;
+    let backfill = &mut conn
+        .prepare_cached("UPDATE statements SET xxh3_128 = ? WHERE id = ? AND xxh3_128 IS NULL")?;
+    for statement in statements {
+        let wide_id: Option<Vec<u8>> = match id_hash {
+            IdHash::Xxh3_64 => None,
+            IdHash::Xxh3_128 => Some(xxh3_128(statement.text.as_bytes()).to_be_bytes().to_vec()),
+        };
+        let inserted = insert.execute(rusqlite::params![
+            statement.id as i64,
+            statement.text,
+            wide_id
+        ])?;
+        if inserted > 0 {
+            continue;
+        }
+        let text: String = existing.query_row([statement.id], |row| row.get(0))?;
+        if text != statement.text {
+            return Err(Failure::HashCollision {
+                id: statement.id,
+                existing: text,
+                new: statement.text.clone(),
+            });
+        }
+        if wide_id.is_some() {
+            backfill.execute(rusqlite::params![wide_id, statement.id])?;
         }
     }
-    return txn.commit();
+    return Ok(());
+}
+
+pub fn insert_document(
+    conn: &Connection,
+    id: i64,
+    encoding: Option<&str>,
+    run_id: Option<i64>,
+    complete: bool,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO documents(id, encoding, run_id, complete) VALUES (?, ?, ?, ?) ON CONFLICT DO NOTHING",
+        rusqlite::params![id, encoding, run_id, complete],
+    )?;
+    return Ok(());
+}
+
+/// record the start of an ingest by this build, returning the run's id
+pub fn begin_run(
+    conn: &Connection,
+    command_line: &[String],
+    input_roots: &[String],
+) -> Result<i64, Failure> {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    use xxhash_rust::xxh3::xxh3_64;
+    let command_line = serde_json::to_string(command_line).map_err(std::io::Error::from)?;
+    let input_roots = serde_json::to_string(input_roots).map_err(std::io::Error::from)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_nanos());
+    // a hash rather than a rowid so that merged dbs' runs don't collide
+    let id = xxh3_64(format!("{}\n{}\n{}", now, command_line, input_roots).as_bytes()) as i64;
+    conn.execute(
+        "INSERT INTO runs(id, splitter_version, splitter_commit, psql_splitter_commit, pg_query_wrapper_commit, libpg_query_version, command_line, input_roots, started_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, strftime('%Y-%m-%dT%H:%M:%fZ', 'now'))",
+        rusqlite::params![
+            id,
+            crate::SPLITTER_VERSION,
+            crate::SPLITTER_COMMIT,
+            crate::PSQL_SPLITTER_COMMIT,
+            crate::PG_QUERY_WRAPPER_COMMIT,
+            crate::LIBPG_QUERY_VERSION.as_str(),
+            command_line,
+            input_roots
+        ],
+    )?;
+    return Ok(id);
+}
+
+pub fn finish_run(conn: &Connection, run_id: i64) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "UPDATE runs SET finished_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now') WHERE id = ?",
+        [run_id],
+    )?;
+    return Ok(());
 }
 
-pub fn doc_already_processed(conn: &mut Connection, doc_id: i64) -> Result<bool, rusqlite::Error> {
+/// whether a document was last written by a different splitter version or
+/// commit, psql_splitter commit, or libpg_query version than this build, or
+/// before runs were recorded
+pub fn document_is_stale(conn: &Connection, document_id: i64) -> Result<bool, rusqlite::Error> {
+    return conn.query_row(
+        "SELECT r.id IS NULL
+            OR r.splitter_version IS NOT ?2
+            OR r.splitter_commit IS NOT ?3
+            OR r.psql_splitter_commit IS NOT ?4
+            OR r.libpg_query_version IS NOT ?5
+        FROM documents AS d LEFT JOIN runs AS r ON r.id = d.run_id
+        WHERE d.id = ?1",
+        rusqlite::params![
+            document_id,
+            crate::SPLITTER_VERSION,
+            crate::SPLITTER_COMMIT,
+            crate::PSQL_SPLITTER_COMMIT,
+            crate::LIBPG_QUERY_VERSION.as_str()
+        ],
+        |row| row.get(0),
+    );
+}
+
+pub fn doc_already_processed(conn: &Connection, doc_id: i64) -> Result<bool, rusqlite::Error> {
     let result = conn.query_row(
         "SELECT id FROM documents WHERE id = ?;",
         &[&doc_id],
@@ -69,46 +435,32 @@ pub fn doc_already_processed(conn: &mut Connection, doc_id: i64) -> Result<bool,
 }
 
 pub fn bulk_insert_statement_languages(
-    conn: &mut Connection,
-    statement_languages: Vec<(i64, Language)>,
+    conn: &Connection,
+    statement_languages: &[(i64, Language)],
 ) -> Result<(), rusqlite::Error> {
-    let txn = conn.transaction()?;
-    {
-        let sql = format!(
-            "INSERT INTO statement_languages(statement_id, language_id) VALUES {} ON CONFLICT DO NOTHING;",
-            ",(?,?)"
-                .repeat(statement_languages.len())
-                .trim_start_matches(',')
-        );
-        let mut params: Vec<i64> = Vec::with_capacity(2 * statement_languages.len());
-
-        // txn.execute(sql.as_str(), params)
-        for row in statement_languages {
-            params.push(row.0);
-            params.push(row.1 as i64)
-        }
-        txn.execute(sql.as_str(), rusqlite::params_from_iter(params.iter()))?;
+    let mut params: Vec<i64> = Vec::with_capacity(2 * statement_languages.len());
+    for row in statement_languages {
+        params.push(row.0);
+        params.push(row.1 as i64)
     }
-    return txn.commit();
+    return batch_insert(
+        conn,
+        "INSERT INTO statement_languages(statement_id, language_id)",
+        2,
+        params.as_slice(),
+    );
 }
 
-pub fn insert_license(
-    conn: &mut Connection,
-    id: &str,
-    license: String,
-) -> Result<(), rusqlite::Error> {
-    let txn = conn.transaction()?;
-    {
-        txn.execute(
-            "INSERT INTO licenses (id, text) VALUES (?, ?) ON CONFLICT(id) DO UPDATE SET text = excluded.text",
-            rusqlite::params![id, license],
-        )?;
-    }
-    return txn.commit();
+pub fn insert_license(conn: &Connection, id: &str, license: String) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO licenses (id, text) VALUES (?, ?) ON CONFLICT(id) DO UPDATE SET text = excluded.text",
+        rusqlite::params![id, license],
+    )?;
+    return Ok(());
 }
 
 pub fn bulk_insert_urls(
-    conn: &mut Connection,
+    conn: &Connection,
     urls: &[&str],
     license_id: Option<&str>,
 ) -> Result<Vec<i64>, rusqlite::Error> {
@@ -117,115 +469,841 @@ pub fn bulk_insert_urls(
         .iter()
         .map(|url| xxh3_64(url.as_bytes()) as i64)
         .collect();
-    let txn = conn.transaction()?;
     if let Some(license_id) = license_id {
-        {
-            let insert = &mut txn.prepare(
-                "INSERT INTO urls (id, url, license_id) VALUES (?, ?, ?) ON CONFLICT DO NOTHING",
-            )?;
-            for (url, id) in urls.iter().zip(ids.as_slice().iter()) {
-                let params = rusqlite::params![&id, *url, license_id];
-                insert.execute(params)?;
-            }
+        let insert = &mut conn.prepare(
+            "INSERT INTO urls (id, url, license_id) VALUES (?, ?, ?) ON CONFLICT DO NOTHING",
+        )?;
+        for (url, id) in urls.iter().zip(ids.as_slice().iter()) {
+            let params = rusqlite::params![&id, *url, license_id];
+            insert.execute(params)?;
         }
     } else {
-        {
-            let insert = &mut txn
-                .prepare("INSERT INTO urls (id, url) VALUES (?, ?) ON CONFLICT DO NOTHING")?;
-            for (url, id) in urls.iter().zip(ids.as_slice().iter()) {
-                let params = rusqlite::params![&id, *url];
-                insert.execute(params)?;
-            }
+        let insert =
+            &mut conn.prepare("INSERT INTO urls (id, url) VALUES (?, ?) ON CONFLICT DO NOTHING")?;
+        for (url, id) in urls.iter().zip(ids.as_slice().iter()) {
+            let params = rusqlite::params![&id, *url];
+            insert.execute(params)?;
         }
     }
-    txn.commit()?;
     return Ok(ids);
 }
 
 // TODO: insert document urls
 pub fn bulk_insert_document_urls(
-    conn: &mut Connection,
+    conn: &Connection,
     document_id: i64,
     urls_ids: &[i64],
 ) -> Result<(), rusqlite::Error> {
-    let txn = conn.transaction()?;
-    {
-        let insert = &mut txn.prepare(
-            "INSERT INTO document_urls(document_id, url_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
-        )?;
-        for url_id in urls_ids {
-            insert.execute(&[&document_id, url_id])?;
-        }
+    let insert = &mut conn.prepare_cached(
+        "INSERT INTO document_urls(document_id, url_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+    )?;
+    for url_id in urls_ids {
+        insert.execute(&[&document_id, url_id])?;
     }
-    return txn.commit();
+    return Ok(());
 }
 
 pub fn bulk_insert_statement_documents(
-    conn: &mut Connection,
-    statement_sources: Vec<StatementSource>,
+    conn: &Connection,
+    statement_sources: &[StatementSource],
 ) -> Result<(), rusqlite::Error> {
-    if statement_sources.len() == 0 {
-        return Ok(());
+    let mut params: Vec<Value> = Vec::with_capacity(13 * statement_sources.len());
+    for src in statement_sources {
+        params.push(Value::Integer(src.document_id));
+        params.push(Value::Integer(src.statement_id));
+        let span = src.span();
+        params.push(Value::Integer(span.start_line as i64));
+        params.push(Value::Integer(span.end_line as i64));
+        params.push(Value::Integer(span.start_offset as i64));
+        params.push(Value::Integer(span.end_offset as i64));
+        let unicode: Vec<Option<usize>> = match &span.unicode {
+            Some(u) => vec![
+                Some(u.start_column),
+                Some(u.end_column),
+                Some(u.start_char),
+                Some(u.end_char),
+                Some(u.start_utf16),
+                Some(u.end_utf16),
+            ],
+            None => vec![None; 6],
+        };
+        params.push(match &src.locator {
+            Some(locator) => Value::Text(locator.clone()),
+            None => Value::Null,
+        });
+        for value in unicode {
+            params.push(match value {
+                Some(value) => Value::Integer(value as i64),
+                None => Value::Null,
+            });
+        }
     }
-    let mut params: Vec<i64> = Vec::with_capacity(6 * statement_sources.len());
+    return batch_insert(
+        conn,
+        "INSERT INTO document_statements (document_id, statement_id, start_line, end_line, start_offset, end_offset, locator, start_column, end_column, start_char, end_char, start_utf16, end_utf16)",
+        13,
+        params.as_slice(),
+    );
+}
 
-    let txn = conn.transaction()?;
-    {
-        let insert_document_statement = &mut txn.prepare(
-                format!(
-                    "INSERT INTO document_statements (document_id, statement_id, start_line, end_line, start_offset, end_offset) VALUES {} ON CONFLICT DO NOTHING",
-                    ",(?,?,?,?,?,?)".repeat(statement_sources.len()).trim_start_matches(",")
-                ).as_str())?;
+pub fn bulk_insert_statement_children(
+    conn: &Connection,
+    children: &[StatementChild],
+) -> Result<(), rusqlite::Error> {
+    let insert = &mut conn.prepare_cached(
+        "INSERT INTO statement_children(parent_id, child_id, start_offset, end_offset) VALUES (?, ?, ?, ?) ON CONFLICT DO NOTHING",
+    )?;
+    for child in children {
+        insert.execute(rusqlite::params![
+            child.parent_id,
+            child.child_id,
+            child.start_offset.map(|offset| offset as i64),
+            child.end_offset.map(|offset| offset as i64),
+        ])?;
+    }
+    return Ok(());
+}
 
-        let insert_document_url = &mut txn.prepare(
-            "INSERT INTO document_urls(document_id, url_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
-        )?;
+/// upsert a `versions` row, returning its id: the xxhash3_64 of `{family}:{version}`
+pub fn insert_version(
+    conn: &Connection,
+    family: &str,
+    version: &str,
+) -> Result<i64, rusqlite::Error> {
+    use xxhash_rust::xxh3::xxh3_64;
+    let id = xxh3_64(format!("{}:{}", family, version).as_bytes()) as i64;
+    conn.execute(
+        "INSERT INTO versions (id, family, version) VALUES (?, ?, ?) ON CONFLICT DO NOTHING",
+        rusqlite::params![id, family, version],
+    )?;
+    return Ok(id);
+}
+
+pub fn bulk_insert_statement_versions(
+    conn: &Connection,
+    statement_ids: &[i64],
+    version_id: i64,
+) -> Result<(), rusqlite::Error> {
+    let insert = &mut conn.prepare_cached(
+        "INSERT INTO statement_versions(statement_id, version_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+    )?;
+    for statement_id in statement_ids {
+        insert.execute(&[statement_id, &version_id])?;
+    }
+    return Ok(());
+}
+
+pub fn bulk_insert_language_versions(
+    conn: &Connection,
+    language_ids: &[i64],
+    version_id: i64,
+) -> Result<(), rusqlite::Error> {
+    let insert = &mut conn.prepare_cached(
+        "INSERT INTO language_versions(language_id, version_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+    )?;
+    for language_id in language_ids {
+        insert.execute(&[language_id, &version_id])?;
+    }
+    return Ok(());
+}
+
+pub fn insert_document_version(
+    conn: &Connection,
+    document_id: i64,
+    version_id: i64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        "INSERT INTO document_versions(document_id, version_id) VALUES (?, ?) ON CONFLICT DO NOTHING",
+        &[&document_id, &version_id],
+    )?;
+    return Ok(());
+}
 
-        for src in statement_sources.as_slice() {
-            // insert_document_statement.execute(rusqlite::params![
-            //     src.document_id as i64,
-            //     src.statement_id as i64,
-            //     src.start_line,
-            //     src.start_line + src.n_lines - 1,
-            //     src.start_offset,
-            //     src.end_offset,
-            //     // format!("#L{}-L{}", src.start_line, src.start_line + src.n_lines - 1)
-            // ])?;
-            let url_id = src.url_id();
-            params.push(src.document_id);
-            params.push(src.statement_id);
-            params.push(src.start_line as i64);
-            params.push((src.start_line + src.n_lines) as i64);
-            params.push(src.start_offset as i64);
-            params.push(src.end_offset as i64);
-            insert_document_url.execute(rusqlite::params![src.document_id, url_id,])?;
-        }
-        insert_document_statement.execute(rusqlite::params_from_iter(params.iter()))?;
-    }
-    return txn.commit();
+/// link a document and the statements and language hints already recorded
+/// for it to another version
+pub fn link_document_to_version(
+    conn: &Connection,
+    document_id: i64,
+    version_id: i64,
+) -> Result<(), rusqlite::Error> {
+    insert_document_version(conn, document_id, version_id)?;
+    conn.execute(
+        "INSERT INTO statement_versions(statement_id, version_id)
+        SELECT DISTINCT statement_id, ?1 FROM document_statements WHERE document_id = ?2
+        ON CONFLICT DO NOTHING",
+        &[&version_id, &document_id],
+    )?;
+    conn.execute(
+        "INSERT INTO language_versions(language_id, version_id)
+        SELECT DISTINCT lang.language_id, ?1
+        FROM statement_languages AS lang
+        JOIN document_statements AS src ON src.statement_id = lang.statement_id
+        WHERE src.document_id = ?2
+        ON CONFLICT DO NOTHING",
+        &[&version_id, &document_id],
+    )?;
+    return Ok(());
 }
 
 pub fn bulk_insert_statement_fingerprints(
-    conn: &mut Connection,
-    statement_fingerprints: Vec<(i64, i64)>,
+    conn: &Connection,
+    statement_fingerprints: &[(i64, i64)],
+) -> Result<(), rusqlite::Error> {
+    let mut params: Vec<i64> = Vec::with_capacity(statement_fingerprints.len() * 2);
+    for (statement_id, fingerprint) in statement_fingerprints {
+        params.push(*statement_id);
+        params.push(*fingerprint);
+    }
+    return batch_insert(
+        conn,
+        "INSERT INTO statement_fingerprints(statement_id, fingerprint)",
+        2,
+        params.as_slice(),
+    );
+}
+
+pub fn bulk_insert_statement_asts(
+    conn: &Connection,
+    asts: &[StatementAst],
+) -> Result<(), rusqlite::Error> {
+    let mut params: Vec<Value> = Vec::with_capacity(asts.len() * 3);
+    for ast in asts {
+        params.push(Value::Integer(ast.statement_id));
+        params.push(Value::Integer(ast.pg_query_version as i64));
+        params.push(Value::Blob(ast.protobuf.clone()));
+    }
+    return batch_insert(
+        conn,
+        "INSERT INTO statement_asts(statement_id, pg_query_version, protobuf)",
+        3,
+        params.as_slice(),
+    );
+}
+
+/// record pg_query's predictions, along with the oracle itself
+pub fn bulk_insert_predictions(
+    conn: &Connection,
+    predictions: &[Prediction],
 ) -> Result<(), rusqlite::Error> {
-    if statement_fingerprints.len() <= 0 {
+    if predictions.len() <= 0 {
         return Ok(());
     }
-    let txn = conn.transaction()?;
-    {
-        let insert = &mut txn.prepare(
-            format!(
-                "INSERT INTO statement_fingerprints(statement_id, fingerprint) VALUES {} ON CONFLICT DO NOTHING;",
-                ",(?,?)".repeat(statement_fingerprints.len()).trim_start_matches(","),
-            ).as_str()
+    conn.execute(
+        "INSERT INTO oracles(id, name) VALUES (?, ?) ON CONFLICT DO NOTHING",
+        rusqlite::params![
+            *crate::PG_QUERY_ORACLE_ID,
+            crate::PG_QUERY_ORACLE_NAME.as_str()
+        ],
+    )?;
+    let mut params: Vec<Value> = Vec::with_capacity(predictions.len() * 6);
+    for prediction in predictions {
+        params.push(Value::Integer(prediction.statement_id));
+        params.push(Value::Integer(prediction.oracle_id));
+        params.push(Value::Integer(prediction.language as i64));
+        params.push(match prediction.cursorpos {
+            Some(cursorpos) => Value::Text(format!("{{\"cursorpos\":{}}}", cursorpos)),
+            None => Value::Null,
+        });
+        params.push(match &prediction.error {
+            Some(error) => Value::Text(error.clone()),
+            None => Value::Null,
+        });
+        params.push(Value::Integer(prediction.valid as i64));
+    }
+    return batch_insert(
+        conn,
+        "INSERT INTO predictions(statement_id, oracle_id, language_id, message, error, valid)",
+        6,
+        params.as_slice(),
+    );
+}
+
+pub fn bulk_insert_statement_normalized(
+    conn: &Connection,
+    normalized: &[(i64, String)],
+) -> Result<(), rusqlite::Error> {
+    let mut params: Vec<Value> = Vec::with_capacity(normalized.len() * 2);
+    for (statement_id, text) in normalized {
+        params.push(Value::Integer(*statement_id));
+        params.push(Value::Text(text.clone()));
+    }
+    return batch_insert(
+        conn,
+        "INSERT INTO statement_normalized(statement_id, normalized)",
+        2,
+        params.as_slice(),
+    );
+}
+
+/// statements sharing a fingerprint
+pub struct FingerprintGroup {
+    pub fingerprint: i64,
+    pub statements: usize,
+    /// the number of distinct normalized texts among the statements
+    pub normalized: usize,
+    /// the normalized text of the group's shortest statement, or its text if
+    /// it wasn't normalized
+    pub example: String,
+}
+
+/// fingerprint groups of at least `min_statements` statements, largest first
+pub fn fingerprint_groups(
+    conn: &Connection,
+    min_statements: usize,
+    limit: Option<usize>,
+) -> Result<Vec<FingerprintGroup>, rusqlite::Error> {
+    let mut query = conn.prepare(
+        "SELECT
+            f.fingerprint
+          , COUNT(DISTINCT f.statement_id) AS n_statements
+          , COUNT(DISTINCT n.normalized)
+          , (
+            SELECT COALESCE(n2.normalized, s.\"text\")
+            FROM statement_fingerprints AS f2
+            JOIN statements AS s ON s.id = f2.statement_id
+            LEFT JOIN statement_normalized AS n2 ON n2.statement_id = s.id
+            WHERE f2.fingerprint = f.fingerprint
+            ORDER BY length(s.\"text\"), s.id
+            LIMIT 1
+          )
+        FROM statement_fingerprints AS f
+        LEFT JOIN statement_normalized AS n ON n.statement_id = f.statement_id
+        GROUP BY f.fingerprint
+        HAVING n_statements >= ?1
+        ORDER BY n_statements DESC, f.fingerprint
+        LIMIT ?2",
+    )?;
+    let limit: i64 = limit.map_or(-1, |limit| limit as i64);
+    let rows = query.query_map(rusqlite::params![min_statements as i64, limit], |row| {
+        Ok(FingerprintGroup {
+            fingerprint: row.get(0)?,
+            statements: row.get::<_, i64>(1)? as usize,
+            normalized: row.get::<_, i64>(2)? as usize,
+            example: row.get(3)?,
+        })
+    })?;
+    return rows.collect();
+}
+
+/// documents whose rows weren't all written, e.g. by a splitter that crashed
+/// partway through
+pub fn incomplete_documents(conn: &Connection) -> Result<Vec<i64>, rusqlite::Error> {
+    let mut query = conn.prepare("SELECT id FROM documents WHERE NOT complete ORDER BY id")?;
+    let rows = query.query_map([], |row| row.get(0))?;
+    return rows.collect();
+}
+
+/// the name of every table in the main db, in the order they were created
+pub fn tables(conn: &Connection) -> Result<Vec<String>, rusqlite::Error> {
+    let mut query =
+        conn.prepare("SELECT name FROM main.sqlite_master WHERE type = 'table' ORDER BY rowid")?;
+    let rows = query.query_map([], |row| row.get(0))?;
+    return rows.collect();
+}
+
+/// a column as described by `pragma table_info`
+pub struct Column {
+    pub name: String,
+    /// the declared type, e.g. `INT4`, or "" if none was declared
+    pub decl_type: String,
+    pub not_null: bool,
+    /// the column's 1-indexed position in the primary key, or 0 if it's not
+    /// part of it
+    pub pk: u32,
+}
+
+/// the columns of a table in the main db, in order
+pub fn columns(conn: &Connection, table: &str) -> Result<Vec<Column>, rusqlite::Error> {
+    let mut query = conn.prepare(
+        "SELECT name, type, \"notnull\", pk FROM pragma_table_info(?, 'main') ORDER BY cid",
+    )?;
+    let rows = query.query_map([table], |row| {
+        Ok(Column {
+            name: row.get(0)?,
+            decl_type: row.get(1)?,
+            not_null: row.get(2)?,
+            pk: row.get(3)?,
+        })
+    })?;
+    return rows.collect();
+}
+
+/// writes to a corpus db in a single transaction, with a savepoint around each
+/// document so that a document is either written completely or not at all
+pub struct SqliteWriter<'a> {
+    /// `None` once committed
+    txn: Option<Transaction<'a>>,
+    license_id: Option<String>,
+    version_id: Option<i64>,
+    id_hash: IdHash,
+    run_id: i64,
+    reprocess_stale: bool,
+    /// documents to forget and write again, e.g. incomplete ones
+    rewrite: HashSet<i64>,
+    /// the id and encoding of the document being written
+    document: Option<(i64, &'static str)>,
+    statement_ids: Vec<i64>,
+    language_ids: Vec<i64>,
+}
+
+impl<'a> SqliteWriter<'a> {
+    /// open a transaction and record the run, license, and version, if any
+    pub fn new(conn: &'a mut Connection, options: &IngestOptions) -> Result<Self, Failure> {
+        let txn = conn.transaction()?;
+        let run_id = begin_run(
+            &txn,
+            options.command_line.as_slice(),
+            options.input_roots.as_slice(),
+        )?;
+        if let (Some(spdx), Some(license)) = (&options.spdx, &options.license) {
+            insert_license(&txn, spdx, license.clone())?;
+        }
+        let version_id = match &options.pg_version {
+            Some(version) => Some(insert_version(&txn, PG_FAMILY, version)?),
+            None => None,
+        };
+        return Ok(SqliteWriter {
+            txn: Some(txn),
+            license_id: options.spdx.clone(),
+            version_id,
+            id_hash: options.id_hash,
+            run_id,
+            reprocess_stale: options.reprocess_stale,
+            rewrite: HashSet::new(),
+            document: None,
+            statement_ids: vec![],
+            language_ids: vec![],
+        });
+    }
+
+    fn conn(&self) -> &Connection {
+        return self.txn.as_ref().expect("already committed");
+    }
+
+    /// forget each of these documents when it's next begun, so that it's
+    /// written again. A document is only forgotten if it's written completely.
+    pub fn rewrite_documents(&mut self, document_ids: &[i64]) {
+        self.rewrite.extend(document_ids.iter());
+    }
+
+    /// delete a document's row, sources, and split failure so that it can be
+    /// written again. Its statements and urls are left in place.
+    fn forget_document(&mut self, document_id: i64) -> Result<(), Failure> {
+        let conn = self.conn();
+        conn.execute(
+            "DELETE FROM document_statements WHERE document_id = ?",
+            [document_id],
+        )?;
+        conn.execute(
+            "DELETE FROM split_failures WHERE document_id = ?",
+            [document_id],
         )?;
-        let mut params: Vec<i64> = Vec::with_capacity(statement_fingerprints.len() * 2);
-        for (statement_id, fingerprint) in statement_fingerprints {
-            params.push(statement_id);
-            params.push(fingerprint);
+        conn.execute("DELETE FROM documents WHERE id = ?", [document_id])?;
+        return Ok(());
+    }
+}
+
+impl<'a> CorpusWriter for SqliteWriter<'a> {
+    fn begin_document(&mut self, doc: &Document, urls: &[String]) -> Result<bool, Failure> {
+        self.conn().execute_batch("SAVEPOINT document")?;
+        let urls: Vec<&str> = urls.iter().map(|url| url.as_str()).collect();
+        let url_ids = bulk_insert_urls(self.conn(), urls.as_slice(), self.license_id.as_deref())?;
+        bulk_insert_document_urls(self.conn(), doc.id(), url_ids.as_slice())?;
+        // forgetting within the savepoint keeps the old rows if the document
+        // is aborted
+        if self.rewrite.contains(&doc.id())
+            || (self.reprocess_stale
+                && doc_already_processed(self.conn(), doc.id())?
+                && document_is_stale(self.conn(), doc.id())?)
+        {
+            self.forget_document(doc.id())?;
+        }
+        if doc_already_processed(self.conn(), doc.id())? {
+            if let Some(version_id) = self.version_id {
+                link_document_to_version(self.conn(), doc.id(), version_id)?;
+            }
+            self.conn().execute_batch("RELEASE document")?;
+            self.document = None;
+            return Ok(false);
+        }
+        self.document = Some((doc.id(), doc.encoding().name()));
+        self.statement_ids.clear();
+        self.language_ids.clear();
+        return Ok(true);
+    }
+    fn already_processed(&mut self, doc: &Document) -> Result<bool, Failure> {
+        if self.rewrite.contains(&doc.id()) || !doc_already_processed(self.conn(), doc.id())? {
+            return Ok(false);
+        }
+        return Ok(!(self.reprocess_stale && document_is_stale(self.conn(), doc.id())?));
+    }
+    fn write_statements(&mut self, statements: &[Statement]) -> Result<(), Failure> {
+        bulk_insert_statements(self.conn(), statements, self.id_hash)?;
+        self.statement_ids.extend(statements.iter().map(|s| s.id));
+        return Ok(());
+    }
+    fn write_sources(&mut self, sources: &[StatementSource]) -> Result<(), Failure> {
+        bulk_insert_statement_documents(self.conn(), sources)?;
+        return Ok(());
+    }
+    fn write_fingerprints(&mut self, fingerprints: &[(i64, i64)]) -> Result<(), Failure> {
+        bulk_insert_statement_fingerprints(self.conn(), fingerprints)?;
+        return Ok(());
+    }
+    fn write_languages(&mut self, languages: &[(i64, Language)]) -> Result<(), Failure> {
+        // TODO: separate inserting statements from statement_languages
+        bulk_insert_statement_languages(self.conn(), languages)?;
+        self.language_ids
+            .extend(languages.iter().map(|(_, language)| *language as i64));
+        return Ok(());
+    }
+    fn write_children(&mut self, children: &[StatementChild]) -> Result<(), Failure> {
+        bulk_insert_statement_children(self.conn(), children)?;
+        return Ok(());
+    }
+    fn write_asts(&mut self, asts: &[StatementAst]) -> Result<(), Failure> {
+        bulk_insert_statement_asts(self.conn(), asts)?;
+        return Ok(());
+    }
+    fn write_predictions(&mut self, predictions: &[Prediction]) -> Result<(), Failure> {
+        bulk_insert_predictions(self.conn(), predictions)?;
+        return Ok(());
+    }
+    fn write_normalized(&mut self, normalized: &[(i64, String)]) -> Result<(), Failure> {
+        bulk_insert_statement_normalized(self.conn(), normalized)?;
+        return Ok(());
+    }
+    fn write_split_failure(&mut self, doc: &Document, failure: &Failure) -> Result<(), Failure> {
+        if let Failure::Split {
+            offset,
+            line,
+            remainder_preview,
+        } = failure
+        {
+            self.conn().execute(
+                "INSERT INTO split_failures(document_id, start_offset, start_line, remainder_preview) VALUES (?, ?, ?, ?) ON CONFLICT DO NOTHING",
+                rusqlite::params![
+                    doc.id(),
+                    doc.original_offset(*offset) as i64,
+                    *line as i64,
+                    remainder_preview
+                ],
+            )?;
         }
-        insert.execute(rusqlite::params_from_iter(params.iter()))?;
+        return Ok(());
+    }
+    fn end_document(&mut self) -> Result<(), Failure> {
+        let (document_id, encoding) = self.document.take().expect("no document begun");
+        insert_document(
+            self.conn(),
+            document_id,
+            Some(encoding),
+            Some(self.run_id),
+            true,
+        )?;
+        self.rewrite.remove(&document_id);
+        if let Some(version_id) = self.version_id {
+            insert_document_version(self.conn(), document_id, version_id)?;
+            self.language_ids.sort();
+            self.language_ids.dedup();
+            bulk_insert_statement_versions(self.conn(), self.statement_ids.as_slice(), version_id)?;
+            bulk_insert_language_versions(self.conn(), self.language_ids.as_slice(), version_id)?;
+        }
+        self.conn().execute_batch("RELEASE document")?;
+        return Ok(());
+    }
+    fn abort_document(&mut self) -> Result<(), Failure> {
+        self.document = None;
+        self.conn()
+            .execute_batch("ROLLBACK TO document; RELEASE document")?;
+        return Ok(());
+    }
+    fn commit(&mut self) -> Result<(), Failure> {
+        if let Some(txn) = self.txn.take() {
+            finish_run(&txn, self.run_id)?;
+            txn.commit()?;
+        }
+        return Ok(());
+    }
+}
+
+/// the rows merged into one table
+pub struct MergedTable {
+    pub name: String,
+    /// rows that weren't already present
+    pub inserted: usize,
+    /// rows whose primary key was already present with different values, e.g.
+    /// two statements' texts with the same xxhash3_64, ignoring
+    /// `MERGE_IGNORED_COLUMNS`. The first row wins.
+    pub conflicts: usize,
+}
+
+/// the most databases sqlite can attach at once
+pub const MAX_ATTACHED: usize = 10;
+
+/// columns that record how a row was written rather than what it is, e.g. the
+/// run that split a document or a locator from a --url-template. Rows that
+/// only differ in these aren't conflicts, and the first row's values win.
+const MERGE_IGNORED_COLUMNS: &[(&str, &str)] =
+    &[("documents", "run_id"), ("document_statements", "locator")];
+
+/// merge every table of each input db into `conn` in a single transaction,
+/// returning per-table counts in the order the tables were created
+pub fn merge(conn: &mut Connection, inputs: &[&str]) -> Result<Vec<MergedTable>, Failure> {
+    if inputs.len() > MAX_ATTACHED {
+        return Err(Failure::Other(format!(
+            "can only merge {} dbs at a time, got {}",
+            MAX_ATTACHED,
+            inputs.len()
+        )));
+    }
+    // ATTACH can't happen inside a transaction
+    let aliases: Vec<String> = (0..inputs.len()).map(|i| format!("input{}", i)).collect();
+    for (path, alias) in inputs.iter().zip(aliases.iter()) {
+        conn.execute(format!("ATTACH DATABASE ? AS {}", alias).as_str(), [path])?;
+        if let Err(e) = check_version(attached_schema_version(conn, alias)?) {
+            return Err(Failure::Other(format!("{}: {}", path, e)));
+        }
+    }
+    let txn = conn.transaction()?;
+    let tables: Vec<String> = tables(&txn)?
+        .into_iter()
+        .filter(|table| table != "schema_version")
+        .collect();
+    let mut merged: Vec<MergedTable> = Vec::with_capacity(tables.len());
+    for table in tables {
+        let columns = columns(&txn, table.as_str())?;
+        let names: Vec<String> = columns
+            .iter()
+            .map(|column| format!("\"{}\"", column.name))
+            .collect();
+        let names = names.join(", ");
+        let key: Vec<String> = columns
+            .iter()
+            .filter(|column| column.pk > 0)
+            .map(|column| format!("ours.\"{0}\" = theirs.\"{0}\"", column.name))
+            .collect();
+        let values: Vec<String> = columns
+            .iter()
+            .filter(|column| column.pk == 0)
+            .filter(|column| {
+                !MERGE_IGNORED_COLUMNS.contains(&(table.as_str(), column.name.as_str()))
+            })
+            // a null on either side, e.g. a missing xxh3_128, isn't a conflict
+            .map(|column| format!("ours.\"{0}\" != theirs.\"{0}\"", column.name))
+            .collect();
+        let mut result = MergedTable {
+            name: table.clone(),
+            inserted: 0,
+            conflicts: 0,
+        };
+        for alias in aliases.iter() {
+            if !key.is_empty() && !values.is_empty() {
+                let conflicts: i64 = txn.query_row(
+                    format!(
+                        "SELECT count(*) FROM {alias}.\"{table}\" AS theirs JOIN main.\"{table}\" AS ours ON {key} WHERE {values}",
+                        alias = alias,
+                        table = table,
+                        key = key.join(" AND "),
+                        values = values.join(" OR "),
+                    )
+                    .as_str(),
+                    [],
+                    |row| row.get(0),
+                )?;
+                result.conflicts += conflicts as usize;
+            }
+            result.inserted += txn.execute(
+                format!(
+                    "INSERT OR IGNORE INTO main.\"{table}\" ({names}) SELECT {names} FROM {alias}.\"{table}\"",
+                    alias = alias,
+                    table = table,
+                    names = names,
+                )
+                .as_str(),
+                [],
+            )?;
+        }
+        merged.push(result);
+    }
+    txn.commit()?;
+    for alias in aliases {
+        conn.execute(format!("DETACH DATABASE {}", alias).as_str(), [])?;
+    }
+    return Ok(merged);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the schema as of version 0.0, before there were any migrations
+    const BASELINE_SCHEMA: &str = include_str!("../tests/fixtures/schema-0.0.sql");
+
+    #[test]
+    fn migrates_the_baseline_schema() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(BASELINE_SCHEMA).unwrap();
+        conn.execute_batch(
+            "INSERT INTO versions VALUES (1, 'postgres', '13'), (2, 'postgres', '14');
+INSERT INTO statements VALUES (10, 'select 1;' || char(10)), (11, 'select 2;'), (12, 'select 3;');
+INSERT INTO statement_versions VALUES (10, 1), (11, 1), (10, 2), (12, 2);
+INSERT INTO statement_languages VALUES (10, 0), (11, 0);
+INSERT INTO documents VALUES (100), (101), (102);
+INSERT INTO document_statements VALUES
+    (100, 10, 1, 0, 2, 10, 'https://example.com/a.sql#L1-L2')
+  , (100, 11, 3, 10, 3, 19, NULL)
+  , (101, 12, 1, 0, 1, 9, NULL);",
+        )
+        .unwrap();
+
+        let applied = migrate(&mut conn).unwrap();
+        assert_eq!(applied.len(), MIGRATIONS.len());
+        assert_eq!(schema_version(&conn).unwrap(), SCHEMA_VERSION);
+        assert!(check_version(schema_version(&conn).unwrap()).is_ok());
+
+        // only the statement ending in a newline had an end_line one too far
+        let ends: Vec<(i64, i64, Option<String>)> = conn
+            .prepare("SELECT statement_id, end_line, locator FROM document_statements ORDER BY statement_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            ends,
+            vec![
+                (10, 1, Some("https://example.com/a.sql#L1-L1".to_string())),
+                (11, 3, None),
+                (12, 1, None),
+            ]
+        );
+
+        // a document belongs to the versions all of its statements belong to,
+        // and a document without statements belongs to none
+        let versions: Vec<(i64, i64)> = conn
+            .prepare("SELECT document_id, version_id FROM document_versions ORDER BY 1, 2")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(versions, vec![(100, 1), (101, 2)]);
+
+        // documents missing a language hint or any sources are incomplete
+        assert_eq!(incomplete_documents(&conn).unwrap(), vec![101, 102]);
+
+        // migrating again is a no-op
+        assert!(migrate(&mut conn).unwrap().is_empty());
+    }
+
+    /// a fresh corpus db in the temp dir, with `sql` applied
+    fn temp_corpus(name: &str, sql: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("splitter-{}-{}.sqlite", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        let mut conn = Connection::open(&path).unwrap();
+        init(&mut conn).unwrap();
+        conn.execute_batch(sql).unwrap();
+        return path.to_str().unwrap().to_string();
+    }
+
+    #[test]
+    fn merges_overlapping_corpora() {
+        // the same document, split by different runs with different url
+        // templates, plus one statement id that really does collide
+        let a = temp_corpus(
+            "merge-a",
+            "INSERT INTO runs(id, started_at) VALUES (-7, 'a');
+INSERT INTO statements(id, \"text\") VALUES (10, 'select 1;'), (11, 'select 2;');
+INSERT INTO documents(id, \"encoding\", run_id) VALUES (100, 'UTF-8', -7);
+INSERT INTO document_statements(document_id, statement_id, start_line, start_offset, end_line, end_offset, locator)
+VALUES (100, 10, 1, 0, 1, 9, 'https://a.example/a.sql#L1');",
+        );
+        let b = temp_corpus(
+            "merge-b",
+            "INSERT INTO runs(id, started_at) VALUES (5, 'b1'), (9, 'b2');
+INSERT INTO statements(id, \"text\") VALUES (10, 'select 1;'), (11, 'select 3;'), (12, 'select 4;');
+INSERT INTO documents(id, \"encoding\", run_id) VALUES (100, 'UTF-8', 5), (101, 'UTF-8', 9);
+INSERT INTO document_statements(document_id, statement_id, start_line, start_offset, end_line, end_offset, locator)
+VALUES (100, 10, 1, 0, 1, 9, 'https://b.example/a.sql#L1')
+     , (101, 12, 1, 0, 1, 9, NULL);",
+        );
+        let out = temp_corpus("merge-out", "");
+        let mut conn = Connection::open(&out).unwrap();
+        let merged = merge(&mut conn, &[a.as_str(), b.as_str()]).unwrap();
+        let counts = |name: &str| {
+            let table = merged.iter().find(|table| table.name == name).unwrap();
+            return (table.inserted, table.conflicts);
+        };
+        assert_eq!(counts("statements"), (3, 1));
+        assert_eq!(counts("documents"), (2, 0));
+        assert_eq!(counts("document_statements"), (2, 0));
+        assert_eq!(counts("runs"), (3, 0));
+
+        // the first copy of a document wins, along with its run
+        let documents: Vec<(i64, i64)> = conn
+            .prepare("SELECT id, run_id FROM documents ORDER BY id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(documents, vec![(100, -7), (101, 9)]);
+        let text: String = conn
+            .query_row("SELECT \"text\" FROM statements WHERE id = 11", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(text, "select 2;");
+
+        drop(conn);
+        for path in [a, b, out].iter() {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn records_syntax_errors() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init(&mut conn).unwrap();
+        let splitter = crate::Splitter::default();
+        let doc = splitter
+            .decode(crate::input::InputDocument {
+                path: "a.sql".to_string(),
+                bytes: b"select 1 frm t;\n".to_vec(),
+            })
+            .unwrap();
+        let mut writer = SqliteWriter::new(&mut conn, &IngestOptions::default()).unwrap();
+        crate::writer::write_document(&mut writer, &splitter, &doc, &mut |_, _| {}).unwrap();
+        writer.commit().unwrap();
+        drop(writer);
+
+        let (valid, error, message): (bool, String, String) = conn
+            .query_row("SELECT valid, error, message FROM predictions", [], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .unwrap();
+        assert!(!valid);
+        assert_eq!(error, "syntax error at or near \"t\"");
+        assert_eq!(message, "{\"cursorpos\":14}");
+    }
+
+    #[test]
+    fn documents_from_other_commits_are_stale() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        init(&mut conn).unwrap();
+        let run_id = begin_run(&conn, &[], &[]).unwrap();
+        insert_document(&conn, 100, Some("UTF-8"), Some(run_id), true).unwrap();
+        insert_document(&conn, 101, Some("UTF-8"), None, true).unwrap();
+        assert!(!document_is_stale(&conn, 100).unwrap());
+        assert!(document_is_stale(&conn, 101).unwrap());
+
+        conn.execute(
+            "UPDATE runs SET splitter_commit = 'some other commit' WHERE id = ?",
+            [run_id],
+        )
+        .unwrap();
+        assert!(document_is_stale(&conn, 100).unwrap());
     }
-    return txn.commit();
 }
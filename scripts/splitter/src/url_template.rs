@@ -0,0 +1,168 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use url::Url;
+
+const LINE_PLACEHOLDERS: [&str; 2] = ["{start_line}", "{end_line}"];
+
+/// everything but unreserved characters and `/`, so that an expanded path or
+/// ref is safe in a url's path, query, or fragment
+const COMPONENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/');
+
+/// percent-encode a path or ref for a url, e.g. `a b#1.sql` -> `a%20b%231.sql`
+pub fn encode(component: &str) -> String {
+    return utf8_percent_encode(component, COMPONENT).to_string();
+}
+
+/// a url with `{ref}`, `{path}`, `{start_line}`, and `{end_line}` placeholders,
+/// e.g. `https://github.com/postgres/postgres/blob/{ref}/{path}#L{start_line}-L{end_line}`.
+/// Line placeholders may only appear in the fragment, so that dropping the
+/// fragment yields a link to the whole document.
+#[derive(Clone, Debug)]
+pub struct UrlTemplate {
+    template: String,
+}
+
+impl UrlTemplate {
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let result = UrlTemplate {
+            template: template.to_string(),
+        };
+        let document_url = result.expand_document_url("REL_14_STABLE", "src/example.sql");
+        if let Some(placeholder) = LINE_PLACEHOLDERS.iter().find(|p| document_url.contains(*p)) {
+            return Err(format!(
+                "{} may only appear in the fragment of {}",
+                placeholder, template
+            ));
+        }
+        result.statement_url("REL_14_STABLE", "src/example.sql", 1, 2)?;
+        return Ok(result);
+    }
+
+    pub fn uses_ref(&self) -> bool {
+        return self.template.contains("{ref}");
+    }
+
+    /// the url of a whole document: a fragment containing line placeholders
+    /// is dropped
+    pub fn document_url(&self, reference: &str, path: &str) -> Result<String, String> {
+        return self.validate(self.expand_document_url(reference, path));
+    }
+
+    /// a link to the lines `start_line..=end_line` of a document
+    pub fn statement_url(
+        &self,
+        reference: &str,
+        path: &str,
+        start_line: usize,
+        end_line: usize,
+    ) -> Result<String, String> {
+        let expanded = self
+            .template
+            .replace("{ref}", encode(reference).as_str())
+            .replace("{path}", encode(path).as_str())
+            .replace("{start_line}", start_line.to_string().as_str())
+            .replace("{end_line}", end_line.to_string().as_str());
+        return self.validate(expanded);
+    }
+
+    fn expand_document_url(&self, reference: &str, path: &str) -> String {
+        let expanded = self
+            .template
+            .replace("{ref}", encode(reference).as_str())
+            .replace("{path}", encode(path).as_str());
+        if let Some(start) = expanded.find('#') {
+            let fragment = &expanded[start..];
+            if LINE_PLACEHOLDERS.iter().any(|p| fragment.contains(p)) {
+                return expanded[..start].to_string();
+            }
+        }
+        return expanded;
+    }
+
+    fn validate(&self, expanded: String) -> Result<String, String> {
+        return match Url::parse(expanded.as_str()) {
+            Ok(url) => Ok(url.to_string()),
+            Err(e) => Err(format!(
+                "invalid url {} from {}: {}",
+                expanded, self.template, e
+            )),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GITHUB: &str =
+        "https://github.com/postgres/postgres/blob/{ref}/{path}#L{start_line}-L{end_line}";
+
+    #[test]
+    fn expands_placeholders() {
+        let template = UrlTemplate::parse(GITHUB).unwrap();
+        assert!(template.uses_ref());
+        assert_eq!(
+            template
+                .statement_url("REL_14_STABLE", "src/test/regress/sql/join.sql", 3, 5)
+                .unwrap(),
+            "https://github.com/postgres/postgres/blob/REL_14_STABLE/src/test/regress/sql/join.sql#L3-L5"
+        );
+        assert_eq!(
+            template
+                .document_url("REL_14_STABLE", "src/test/regress/sql/join.sql")
+                .unwrap(),
+            "https://github.com/postgres/postgres/blob/REL_14_STABLE/src/test/regress/sql/join.sql"
+        );
+    }
+
+    #[test]
+    fn keeps_fragments_without_line_placeholders() {
+        let template = UrlTemplate::parse("https://example.com/{path}#top").unwrap();
+        assert!(!template.uses_ref());
+        assert_eq!(
+            template.document_url("", "a.sql").unwrap(),
+            "https://example.com/a.sql#top"
+        );
+        assert_eq!(
+            template.statement_url("", "a.sql", 1, 1).unwrap(),
+            "https://example.com/a.sql#top"
+        );
+    }
+
+    #[test]
+    fn percent_encodes_components() {
+        assert_eq!(encode("src/a b#1?.sql"), "src/a%20b%231%3F.sql");
+        assert_eq!(encode("REL 14/é~x_y-z.sql"), "REL%2014/%C3%A9~x_y-z.sql");
+        let template = UrlTemplate::parse(GITHUB).unwrap();
+        assert_eq!(
+            template
+                .statement_url("REL 14", "a b/c#1?.sql", 1, 2)
+                .unwrap(),
+            "https://github.com/postgres/postgres/blob/REL%2014/a%20b/c%231%3F.sql#L1-L2"
+        );
+    }
+
+    #[test]
+    fn rejects_line_placeholders_outside_the_fragment() {
+        for template in [
+            "https://example.com/{path}?line={start_line}",
+            "https://example.com/{end_line}/{path}",
+            "https://example.com/{path}?l={start_line}#L{end_line}",
+        ]
+        .iter()
+        {
+            let e = UrlTemplate::parse(template).unwrap_err();
+            assert!(e.contains("may only appear in the fragment"), "{}", e);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        assert!(UrlTemplate::parse("not a url/{path}").is_err());
+        assert!(UrlTemplate::parse("https://[/{path}").is_err());
+    }
+}
@@ -0,0 +1,618 @@
+use std::collections::{BTreeMap, HashSet};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::{
+    input::InputDocument, Document, Failure, Ingested, Language, Prediction, SplitDocument,
+    Splitter, Statement, StatementAst, StatementChild, StatementSource,
+};
+
+/// a destination for split documents. Each document is written by a call to
+/// `begin_document`, then the `write_*` methods, then `end_document`; if a
+/// document can't be split or written, `abort_document` is called instead of
+/// `end_document`. Nothing is guaranteed to be persisted until `commit`.
+pub trait CorpusWriter {
+    /// start a document found at `urls`. Returns `false` if the document was
+    /// already written, in which case it's only linked to `urls` and nothing
+    /// else should be written for it.
+    fn begin_document(&mut self, doc: &Document, urls: &[String]) -> Result<bool, Failure>;
+    /// whether `begin_document` would return `false` for `doc`, so that it
+    /// needn't be split
+    fn already_processed(&mut self, _doc: &Document) -> Result<bool, Failure> {
+        return Ok(false);
+    }
+    fn write_statements(&mut self, statements: &[Statement]) -> Result<(), Failure>;
+    fn write_sources(&mut self, sources: &[StatementSource]) -> Result<(), Failure>;
+    /// (statement_id, fingerprint) pairs
+    fn write_fingerprints(&mut self, fingerprints: &[(i64, i64)]) -> Result<(), Failure>;
+    /// (statement_id, language) hints
+    fn write_languages(&mut self, languages: &[(i64, Language)]) -> Result<(), Failure>;
+    fn write_children(&mut self, _children: &[StatementChild]) -> Result<(), Failure> {
+        return Ok(());
+    }
+    fn write_asts(&mut self, _asts: &[StatementAst]) -> Result<(), Failure> {
+        return Ok(());
+    }
+    fn write_predictions(&mut self, _predictions: &[Prediction]) -> Result<(), Failure> {
+        return Ok(());
+    }
+    /// (statement_id, normalized text) pairs
+    fn write_normalized(&mut self, _normalized: &[(i64, String)]) -> Result<(), Failure> {
+        return Ok(());
+    }
+    /// record that `doc` could only be split up to a `Failure::Split`
+    fn write_split_failure(&mut self, _doc: &Document, _failure: &Failure) -> Result<(), Failure> {
+        return Ok(());
+    }
+    fn end_document(&mut self) -> Result<(), Failure> {
+        return Ok(());
+    }
+    /// discard everything written since `begin_document`
+    fn abort_document(&mut self) -> Result<(), Failure> {
+        return Ok(());
+    }
+    fn commit(&mut self) -> Result<(), Failure>;
+}
+
+/// write everything split out of a document, then end it
+pub fn write_split<W: CorpusWriter + ?Sized>(
+    writer: &mut W,
+    doc: &Document,
+    split: &SplitDocument,
+) -> Result<(), Failure> {
+    writer.write_statements(split.statements())?;
+    writer.write_sources(split.sources())?;
+    writer.write_fingerprints(split.fingerprints())?;
+    writer.write_languages(split.languages())?;
+    writer.write_children(split.children())?;
+    writer.write_asts(split.asts())?;
+    writer.write_predictions(split.predictions())?;
+    writer.write_normalized(split.normalized())?;
+    if let Some(failure) = split.split_failure() {
+        writer.write_split_failure(doc, failure)?;
+    }
+    return writer.end_document();
+}
+
+/// split a document, treating a panic in the splitter as the document's
+/// failure rather than the whole ingest's
+fn split(splitter: &Splitter, doc: &Document) -> Result<SplitDocument, Failure> {
+    return match panic::catch_unwind(AssertUnwindSafe(|| splitter.split(doc))) {
+        Ok(split) => split,
+        Err(payload) => {
+            let message = match payload.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => match payload.downcast_ref::<String>() {
+                    Some(message) => message.clone(),
+                    None => "unknown panic".to_string(),
+                },
+            };
+            Err(Failure::Other(format!("splitting panicked: {}", message)))
+        }
+    };
+}
+
+/// split and write a decoded document, calling `report` with the outcome.
+/// Returns whether the document was written; a document that can't be split
+/// or written is reported as skipped and leaves nothing behind.
+pub fn write_document<W, F>(
+    writer: &mut W,
+    splitter: &Splitter,
+    doc: &Document,
+    report: &mut F,
+) -> Result<bool, Failure>
+where
+    W: CorpusWriter + ?Sized,
+    F: FnMut(&str, Ingested),
+{
+    if !writer.begin_document(doc, splitter.urls(doc.path())?.as_slice())? {
+        report(doc.path(), Ingested::AlreadyProcessed);
+        return Ok(false);
+    }
+    return end_document(writer, doc, split(splitter, doc), report);
+}
+
+/// write the outcome of splitting a document after `begin_document`
+fn end_document<W, F>(
+    writer: &mut W,
+    doc: &Document,
+    split: Result<SplitDocument, Failure>,
+    report: &mut F,
+) -> Result<bool, Failure>
+where
+    W: CorpusWriter + ?Sized,
+    F: FnMut(&str, Ingested),
+{
+    let split = match split {
+        Ok(split) => split,
+        Err(e) => {
+            writer.abort_document()?;
+            report(doc.path(), Ingested::Skipped(&e));
+            return Ok(false);
+        }
+    };
+    if let Err(e) = write_split(writer, doc, &split) {
+        writer.abort_document()?;
+        report(doc.path(), Ingested::Skipped(&e));
+        return Ok(false);
+    }
+    report(doc.path(), Ingested::Split(&split));
+    return Ok(true);
+}
+
+/// split and write every document, then commit, calling `report` with the
+/// outcome for each document's path
+pub fn ingest<W, D, F>(
+    writer: &mut W,
+    splitter: &Splitter,
+    documents: D,
+    mut report: F,
+) -> Result<(), Failure>
+where
+    W: CorpusWriter + ?Sized,
+    D: IntoIterator<Item = Result<InputDocument, Failure>>,
+    F: FnMut(&str, Ingested),
+{
+    for input in documents {
+        let input = input?;
+        let path = input.path.clone();
+        let doc = match splitter.decode(input) {
+            Ok(doc) => doc,
+            Err(e) => {
+                report(path.as_str(), Ingested::Skipped(&e));
+                continue;
+            }
+        };
+        write_document(writer, splitter, &doc, &mut report)?;
+    }
+    return writer.commit();
+}
+
+/// a document waiting to be written: its path and either why it couldn't be
+/// decoded or the document and, if it was sent to a worker, its split
+type Pending = (
+    String,
+    Result<(Document, Option<Result<SplitDocument, Failure>>), Failure>,
+);
+
+/// write every pending document whose predecessors have all been written,
+/// advancing `next` past them and forgetting that they were being split
+fn write_pending<W, F>(
+    writer: &mut W,
+    splitter: &Splitter,
+    pending: &mut BTreeMap<usize, Pending>,
+    next: &mut usize,
+    splitting: &mut HashSet<i64>,
+    report: &mut F,
+) -> Result<(), Failure>
+where
+    W: CorpusWriter + ?Sized,
+    F: FnMut(&str, Ingested),
+{
+    while let Some((path, decoded)) = pending.remove(next) {
+        *next += 1;
+        match decoded {
+            Err(e) => report(path.as_str(), Ingested::Skipped(&e)),
+            // not split since it looked already written, but it might not have
+            // been, e.g. if an earlier copy of it couldn't be split
+            Ok((doc, None)) => {
+                write_document(writer, splitter, &doc, report)?;
+            }
+            Ok((doc, Some(split))) => {
+                splitting.remove(&doc.id());
+                if !writer.begin_document(&doc, splitter.urls(doc.path())?.as_slice())? {
+                    report(doc.path(), Ingested::AlreadyProcessed);
+                    continue;
+                }
+                end_document(writer, &doc, split, report)?;
+            }
+        }
+    }
+    return Ok(());
+}
+
+/// a document's index and a worker's split of it
+type Split = (usize, Document, Result<SplitDocument, Failure>);
+
+/// wait for a worker to split a document, then queue it to be written
+fn receive_split(
+    recv: &mpsc::Receiver<Split>,
+    pending: &mut BTreeMap<usize, Pending>,
+) -> Result<(), Failure> {
+    let (i, doc, split) = recv
+        .recv()
+        .map_err(|_| Failure::Other("every splitter thread exited".to_string()))?;
+    pending.insert(i, (doc.path().to_string(), Ok((doc, Some(split)))));
+    return Ok(());
+}
+
+/// like `ingest`, but split documents on `jobs` threads. Documents are decoded
+/// on this thread so that ones already written aren't split again, and are
+/// still written one at a time in the order given, so the output doesn't
+/// depend on `jobs`, except for any record of the run itself, such as
+/// `SqliteWriter`'s timestamped `runs` row.
+pub fn ingest_parallel<W, D, F>(
+    writer: &mut W,
+    splitter: &Splitter,
+    documents: D,
+    jobs: usize,
+    mut report: F,
+) -> Result<(), Failure>
+where
+    W: CorpusWriter + ?Sized,
+    D: IntoIterator<Item = Result<InputDocument, Failure>>,
+    F: FnMut(&str, Ingested),
+{
+    if jobs <= 1 {
+        return ingest(writer, splitter, documents, report);
+    }
+    let shared = Arc::new(splitter.clone());
+    let (send_job, recv_job) = mpsc::channel::<(usize, Document)>();
+    let recv_job = Arc::new(Mutex::new(recv_job));
+    let (send, recv) = mpsc::channel::<Split>();
+    let workers: Vec<thread::JoinHandle<()>> = (0..jobs)
+        .map(|_| {
+            let splitter = Arc::clone(&shared);
+            let recv_job = Arc::clone(&recv_job);
+            let send = send.clone();
+            thread::spawn(move || loop {
+                let job = recv_job.lock().unwrap().recv();
+                let (i, doc) = match job {
+                    Ok(job) => job,
+                    Err(_) => return, // every document was handed out
+                };
+                let split = split(&splitter, &doc);
+                if send.send((i, doc, split)).is_err() {
+                    return; // the writer gave up
+                }
+            })
+        })
+        .collect();
+    drop(send);
+
+    // the most documents handed out past the next one to write, bounding how
+    // many splits are held in memory waiting for a slow document
+    let window = jobs * 2;
+    let mut total: usize = 0;
+    let mut pending: BTreeMap<usize, Pending> = BTreeMap::new();
+    let mut next: usize = 0;
+    // documents sent to workers, which later copies needn't be split for
+    let mut splitting: HashSet<i64> = HashSet::new();
+    for (i, input) in documents.into_iter().enumerate() {
+        let input = input?;
+        total += 1;
+        while i >= next + window {
+            receive_split(&recv, &mut pending)?;
+            write_pending(
+                writer,
+                splitter,
+                &mut pending,
+                &mut next,
+                &mut splitting,
+                &mut report,
+            )?;
+        }
+        let path = input.path.clone();
+        match splitter.decode(input) {
+            Ok(doc) if !splitting.contains(&doc.id()) && !writer.already_processed(&doc)? => {
+                splitting.insert(doc.id());
+                send_job
+                    .send((i, doc))
+                    .map_err(|_| Failure::Other("every splitter thread exited".to_string()))?;
+                continue;
+            }
+            Ok(doc) => pending.insert(i, (path, Ok((doc, None)))),
+            Err(e) => pending.insert(i, (path, Err(e))),
+        };
+        write_pending(
+            writer,
+            splitter,
+            &mut pending,
+            &mut next,
+            &mut splitting,
+            &mut report,
+        )?;
+    }
+    drop(send_job);
+    while next < total {
+        receive_split(&recv, &mut pending)?;
+        write_pending(
+            writer,
+            splitter,
+            &mut pending,
+            &mut next,
+            &mut splitting,
+            &mut report,
+        )?;
+    }
+    for worker in workers {
+        worker
+            .join()
+            .map_err(|_| Failure::Other("a splitter thread panicked".to_string()))?;
+    }
+    return writer.commit();
+}
+
+/// everything written for a single document
+#[derive(Clone, Debug, Default)]
+pub struct DocumentRows {
+    pub id: i64,
+    pub path: String,
+    /// the name of the original file's encoding, e.g. "UTF-8"
+    pub encoding: String,
+    pub urls: Vec<String>,
+    pub statements: Vec<Statement>,
+    pub sources: Vec<StatementSource>,
+    pub fingerprints: Vec<(i64, i64)>,
+    pub languages: Vec<(i64, Language)>,
+    pub children: Vec<StatementChild>,
+    pub asts: Vec<StatementAst>,
+    pub predictions: Vec<Prediction>,
+    pub normalized: Vec<(i64, String)>,
+    /// the 1-indexed line on which splitting stopped, if it did
+    pub split_failure_line: Option<usize>,
+}
+
+impl DocumentRows {
+    pub fn new(doc: &Document, urls: &[String]) -> Self {
+        return DocumentRows {
+            id: doc.id(),
+            path: doc.path().to_string(),
+            encoding: doc.encoding().name().to_string(),
+            urls: urls.to_vec(),
+            ..Default::default()
+        };
+    }
+}
+
+/// keeps everything in memory, e.g. for inspecting a `Splitter`'s output
+#[derive(Default)]
+pub struct MemoryWriter {
+    /// committed documents, in the order they were written
+    pub documents: Vec<DocumentRows>,
+    pending: Vec<DocumentRows>,
+    current: Option<DocumentRows>,
+    /// the ids of every committed or pending document
+    seen: HashSet<i64>,
+}
+
+impl MemoryWriter {
+    pub fn new() -> Self {
+        return Default::default();
+    }
+
+    fn current(&mut self) -> &mut DocumentRows {
+        return self
+            .current
+            .as_mut()
+            .expect("write_* called outside of a document");
+    }
+}
+
+impl CorpusWriter for MemoryWriter {
+    fn begin_document(&mut self, doc: &Document, urls: &[String]) -> Result<bool, Failure> {
+        if self.seen.contains(&doc.id()) {
+            self.current = None;
+            return Ok(false);
+        }
+        self.current = Some(DocumentRows::new(doc, urls));
+        return Ok(true);
+    }
+    fn already_processed(&mut self, doc: &Document) -> Result<bool, Failure> {
+        return Ok(self.seen.contains(&doc.id()));
+    }
+    fn write_statements(&mut self, statements: &[Statement]) -> Result<(), Failure> {
+        self.current().statements.extend_from_slice(statements);
+        return Ok(());
+    }
+    fn write_sources(&mut self, sources: &[StatementSource]) -> Result<(), Failure> {
+        self.current().sources.extend_from_slice(sources);
+        return Ok(());
+    }
+    fn write_fingerprints(&mut self, fingerprints: &[(i64, i64)]) -> Result<(), Failure> {
+        self.current().fingerprints.extend_from_slice(fingerprints);
+        return Ok(());
+    }
+    fn write_languages(&mut self, languages: &[(i64, Language)]) -> Result<(), Failure> {
+        self.current().languages.extend_from_slice(languages);
+        return Ok(());
+    }
+    fn write_children(&mut self, children: &[StatementChild]) -> Result<(), Failure> {
+        self.current().children.extend_from_slice(children);
+        return Ok(());
+    }
+    fn write_asts(&mut self, asts: &[StatementAst]) -> Result<(), Failure> {
+        self.current().asts.extend_from_slice(asts);
+        return Ok(());
+    }
+    fn write_predictions(&mut self, predictions: &[Prediction]) -> Result<(), Failure> {
+        self.current().predictions.extend_from_slice(predictions);
+        return Ok(());
+    }
+    fn write_normalized(&mut self, normalized: &[(i64, String)]) -> Result<(), Failure> {
+        self.current().normalized.extend_from_slice(normalized);
+        return Ok(());
+    }
+    fn write_split_failure(&mut self, _doc: &Document, failure: &Failure) -> Result<(), Failure> {
+        if let Failure::Split { line, .. } = failure {
+            self.current().split_failure_line = Some(*line);
+        }
+        return Ok(());
+    }
+    fn end_document(&mut self) -> Result<(), Failure> {
+        if let Some(rows) = self.current.take() {
+            self.seen.insert(rows.id);
+            self.pending.push(rows);
+        }
+        return Ok(());
+    }
+    fn abort_document(&mut self) -> Result<(), Failure> {
+        self.current = None;
+        return Ok(());
+    }
+    fn commit(&mut self) -> Result<(), Failure> {
+        self.documents.append(&mut self.pending);
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(splitter: &Splitter, path: &str, text: &str) -> Document {
+        return splitter
+            .decode(InputDocument {
+                path: path.to_string(),
+                bytes: text.as_bytes().to_vec(),
+            })
+            .unwrap();
+    }
+
+    #[test]
+    fn memory_writer_keeps_committed_splits() {
+        let splitter = Splitter::default();
+        let doc = decode(&splitter, "a.sql", "select 1;\nselect 2;\n");
+        let split = splitter.split(&doc).unwrap();
+        let mut writer = MemoryWriter::new();
+        assert!(writer.begin_document(&doc, &[]).unwrap());
+        write_split(&mut writer, &doc, &split).unwrap();
+        assert!(writer.documents.is_empty());
+        writer.commit().unwrap();
+
+        assert_eq!(writer.documents.len(), 1);
+        let rows = &writer.documents[0];
+        assert_eq!(rows.id, doc.id());
+        assert_eq!(rows.path, "a.sql");
+        let ids = |statements: &[Statement]| -> Vec<i64> {
+            return statements.iter().map(|s| s.id()).collect();
+        };
+        assert_eq!(ids(&rows.statements), ids(split.statements()));
+        assert_eq!(rows.sources.len(), split.sources().len());
+        assert_eq!(rows.fingerprints, split.fingerprints());
+    }
+
+    #[test]
+    fn memory_writer_aborts_leave_nothing_behind() {
+        let splitter = Splitter::default();
+        let doc = decode(&splitter, "a.sql", "select 1;");
+        let split = splitter.split(&doc).unwrap();
+        let mut writer = MemoryWriter::new();
+        assert!(writer.begin_document(&doc, &[]).unwrap());
+        writer.write_statements(split.statements()).unwrap();
+        writer.write_sources(split.sources()).unwrap();
+        writer.abort_document().unwrap();
+        writer.commit().unwrap();
+        assert!(writer.documents.is_empty());
+
+        // an aborted document can be written again
+        assert!(writer.begin_document(&doc, &[]).unwrap());
+        write_split(&mut writer, &doc, &split).unwrap();
+        writer.commit().unwrap();
+        assert_eq!(writer.documents.len(), 1);
+    }
+
+    #[test]
+    fn memory_writer_skips_processed_documents() {
+        let splitter = Splitter::default();
+        let doc = decode(&splitter, "a.sql", "select 1;");
+        let copy = decode(&splitter, "b.sql", "select 1;");
+        let other = decode(&splitter, "c.sql", "select 2;");
+        let mut writer = MemoryWriter::new();
+        let mut outcomes = vec![];
+        let mut report = |path: &str, ingested: Ingested| {
+            let processed = matches!(ingested, Ingested::AlreadyProcessed);
+            outcomes.push((path.to_string(), processed));
+        };
+        assert!(write_document(&mut writer, &splitter, &doc, &mut report).unwrap());
+        // pending documents count as processed before they're committed
+        assert!(!write_document(&mut writer, &splitter, &copy, &mut report).unwrap());
+        writer.commit().unwrap();
+        assert!(!write_document(&mut writer, &splitter, &doc, &mut report).unwrap());
+        assert!(write_document(&mut writer, &splitter, &other, &mut report).unwrap());
+        writer.commit().unwrap();
+
+        assert_eq!(
+            outcomes,
+            vec![
+                ("a.sql".to_string(), false),
+                ("b.sql".to_string(), true),
+                ("a.sql".to_string(), true),
+                ("c.sql".to_string(), false),
+            ]
+        );
+        let paths: Vec<&str> = writer.documents.iter().map(|d| d.path.as_str()).collect();
+        assert_eq!(paths, vec!["a.sql", "c.sql"]);
+    }
+
+    /// ingest `inputs` on `jobs` threads, returning each path's outcome in
+    /// the order reported and the documents written
+    fn ingest_with_jobs(
+        splitter: &Splitter,
+        inputs: &[(&str, &[u8])],
+        jobs: usize,
+    ) -> (Vec<(String, &'static str)>, Vec<DocumentRows>) {
+        let documents = inputs.iter().map(|(path, bytes)| {
+            return Ok(InputDocument {
+                path: path.to_string(),
+                bytes: bytes.to_vec(),
+            });
+        });
+        let mut writer = MemoryWriter::new();
+        let mut outcomes = vec![];
+        let report = |path: &str, ingested: Ingested| {
+            let outcome = match ingested {
+                Ingested::Split(_) => "split",
+                Ingested::AlreadyProcessed => "already processed",
+                Ingested::Skipped(_) => "skipped",
+            };
+            outcomes.push((path.to_string(), outcome));
+        };
+        ingest_parallel(&mut writer, splitter, documents, jobs, report).unwrap();
+        return (outcomes, writer.documents);
+    }
+
+    #[test]
+    fn output_doesnt_depend_on_jobs() {
+        let splitter = Splitter {
+            encoding: Some(encoding_rs::UTF_8),
+            ..Default::default()
+        };
+        let mut inputs: Vec<(String, Vec<u8>)> = vec![];
+        for i in 0..20 {
+            // copies of the same few texts, some of which aren't utf-8
+            let bytes = match i % 4 {
+                3 => vec![b's', 0xff, b';'],
+                n => format!("select {};\nselect {};\n", n, i % 3).into_bytes(),
+            };
+            inputs.push((format!("{:02}.sql", i), bytes));
+        }
+        let inputs: Vec<(&str, &[u8])> = inputs
+            .iter()
+            .map(|(path, bytes)| (path.as_str(), bytes.as_slice()))
+            .collect();
+        let (outcomes, documents) = ingest_with_jobs(&splitter, inputs.as_slice(), 1);
+        assert_eq!(outcomes.len(), inputs.len());
+        assert!(outcomes.iter().any(|(_, outcome)| *outcome == "skipped"));
+        assert!(outcomes
+            .iter()
+            .any(|(_, outcome)| *outcome == "already processed"));
+
+        let rows = |documents: &[DocumentRows]| -> Vec<(String, Vec<i64>, usize)> {
+            return documents
+                .iter()
+                .map(|d| {
+                    let ids = d.statements.iter().map(|s| s.id()).collect();
+                    (d.path.clone(), ids, d.sources.len())
+                })
+                .collect();
+        };
+        for jobs in [2, 4].iter() {
+            let (parallel_outcomes, parallel_documents) =
+                ingest_with_jobs(&splitter, inputs.as_slice(), *jobs);
+            assert_eq!(parallel_outcomes, outcomes);
+            assert_eq!(rows(&parallel_documents), rows(&documents));
+        }
+    }
+}